@@ -0,0 +1,85 @@
+//! Parallel invocations of delegated request caps over loopback, with concurrent creations and deletions on the owner.
+//! Every invocation takes the owner's full RequestInvoke path: cap table lookup, MAC and holder checks and the handler.
+//! Compares a single shard, i.e. one lock for the whole table, with the default sharded table.
+//!
+//! Run with `cargo bench --bench cap_table`, the shards only make a difference with several worker threads.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use rand::Rng;
+use tcap::capabilities::tcap::Capability;
+use tcap::config::Config;
+use tcap::object::tcap::object::RequestObject;
+use tcap::service::tcap::Service;
+use tokio::sync::Mutex;
+
+const CAPS: usize = 256;
+const TASKS: usize = 16;
+const ITERATIONS: usize = 2_000;
+/// every n-th invocation creates and deletes a capability on the owner
+const WRITE_EVERY: usize = 8;
+
+async fn start(port: u16, shards: usize) -> Service {
+    let address = format!("127.0.0.1:{}", port);
+    let shards = shards.to_string();
+    let config = Config::parse_from(["bench", "-i", "lo", "-a", address.as_str(), "-s", "127.0.0.1:1", "--cap-table-shards", shards.as_str()]);
+    let service = Service::new(config).await;
+    let runner = service.clone();
+    tokio::spawn(async move { runner.run().await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    service
+}
+
+async fn run(shards: usize, port: u16) -> Duration {
+    let owner = Arc::new(start(port, shards).await);
+    let holder = start(port + 1, shards).await;
+    let holder_address = format!("127.0.0.1:{}", port + 1);
+
+    let mut held: Vec<Capability> = Vec::new();
+    for _ in 0..CAPS {
+        let cap = owner.create_capability().await;
+        let object = Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await));
+        cap.lock().await.bind_req(object).await;
+        cap.lock().await.delegate(holder_address.as_str().into()).await.unwrap();
+        let cap_id = cap.lock().await.cap_id;
+        while holder.get_capability(cap_id).await.is_none() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        held.push(holder.get_capability(cap_id).await.unwrap().lock().await.clone());
+    }
+    let held = Arc::new(held);
+
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for _ in 0..TASKS {
+        let owner = owner.clone();
+        let held = held.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..ITERATIONS {
+                let cap = &held[rand::thread_rng().gen_range(0..CAPS)];
+                cap.request_invoke().await.expect("benchmark caps are never deleted");
+                if i % WRITE_EVERY == 0 {
+                    let cap = owner.create_capability().await;
+                    owner.delete_capability(cap).await;
+                }
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    start.elapsed()
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let single = run(1, 39901).await;
+    let sharded = run(16, 39903).await;
+    let invocations = (TASKS * ITERATIONS) as f64;
+    println!("{} worker threads", std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+    println!("{} tasks, {} invocations each, one creation and deletion every {} invocations", TASKS, ITERATIONS, WRITE_EVERY);
+    println!("1 shard:   {:?} ({:.0} invocations/s)", single, invocations / single.as_secs_f64());
+    println!("16 shards: {:?} ({:.0} invocations/s)", sharded, invocations / sharded.as_secs_f64());
+}
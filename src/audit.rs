@@ -0,0 +1,285 @@
+pub mod tcap {
+    pub mod audit {
+        use core::fmt;
+        use std::{
+            fs::{self, File, OpenOptions},
+            io::{self, Write},
+            path::PathBuf,
+            sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
+        };
+
+        use log::error;
+        use tokio::sync::mpsc::{self, error::TrySendError};
+
+        use crate::{capabilities::tcap::CapID, replay::tcap::replay::now_ms};
+
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum AuditEvent {
+            Create,
+            Delegate,
+            InsertCap,
+            Invoke,
+            MemoryCopy,
+            Revoke,
+            CapInvalid,
+            Close,
+            Reclaim,
+        }
+
+        impl fmt::Display for AuditEvent {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let name = match self {
+                    AuditEvent::Create => "create",
+                    AuditEvent::Delegate => "delegate",
+                    AuditEvent::InsertCap => "insert_cap",
+                    AuditEvent::Invoke => "invoke",
+                    AuditEvent::MemoryCopy => "memory_copy",
+                    AuditEvent::Revoke => "revoke",
+                    AuditEvent::CapInvalid => "cap_invalid",
+                    AuditEvent::Close => "close",
+                    AuditEvent::Reclaim => "reclaim",
+                };
+                f.write_str(name)
+            }
+        }
+
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum AuditOutcome {
+            Success,
+            /// the operation was carried out, but the handler reported an error
+            Failed,
+            /// the operation was refused, e.g. because the capability is unknown or the peer is not authorized
+            Rejected,
+        }
+
+        impl fmt::Display for AuditOutcome {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let name = match self {
+                    AuditOutcome::Success => "success",
+                    AuditOutcome::Failed => "failed",
+                    AuditOutcome::Rejected => "rejected",
+                };
+                f.write_str(name)
+            }
+        }
+
+        /// One line of the audit log:
+        /// `ts=<unix ms> event=<event> cap_id=<cap id> peer=<address:port> outcome=<outcome>`
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct AuditRecord {
+            pub timestamp_ms: u64,
+            pub event: AuditEvent,
+            pub cap_id: CapID,
+            pub peer: String,
+            pub outcome: AuditOutcome,
+        }
+
+        impl AuditRecord {
+            pub fn new(event: AuditEvent, cap_id: CapID, peer: &str, outcome: AuditOutcome) -> AuditRecord {
+                AuditRecord {
+                    timestamp_ms: now_ms(),
+                    event,
+                    cap_id,
+                    peer: peer.to_string(),
+                    outcome,
+                }
+            }
+        }
+
+        impl fmt::Display for AuditRecord {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(
+                    f,
+                    "ts={} event={} cap_id={} peer={} outcome={}",
+                    self.timestamp_ms, self.event, self.cap_id, self.peer, self.outcome
+                )
+            }
+        }
+
+        struct AuditFile {
+            file: File,
+            written: u64,
+        }
+
+        /// Append-only audit log file.
+        /// When a record would grow the file beyond `max_bytes`, it is rotated to `<path>.1`,
+        /// older files move up to `<path>.<files>` and the oldest one is deleted.
+        #[derive(Debug)]
+        pub(crate) struct AuditLog {
+            path: PathBuf,
+            max_bytes: u64,
+            files: usize,
+            file: Mutex<AuditFile>,
+        }
+
+        impl fmt::Debug for AuditFile {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct("AuditFile").field("written", &self.written).finish()
+            }
+        }
+
+        impl AuditLog {
+            pub(crate) fn open(path: &str, max_bytes: u64, files: usize) -> io::Result<AuditLog> {
+                let path = PathBuf::from(path);
+                let file = AuditLog::open_file(&path)?;
+                Ok(AuditLog {
+                    path,
+                    max_bytes,
+                    files,
+                    file: Mutex::new(file),
+                })
+            }
+
+            fn open_file(path: &PathBuf) -> io::Result<AuditFile> {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                let written = file.metadata()?.len();
+                Ok(AuditFile { file, written })
+            }
+
+            fn rotated_path(&self, index: usize) -> PathBuf {
+                let mut path = self.path.clone().into_os_string();
+                path.push(format!(".{}", index));
+                PathBuf::from(path)
+            }
+
+            fn rotate(&self, file: &mut AuditFile) -> io::Result<()> {
+                file.file.flush()?;
+                if self.files == 0 {
+                    fs::remove_file(&self.path)?;
+                } else {
+                    let _ = fs::remove_file(self.rotated_path(self.files));
+                    for index in (1..self.files).rev() {
+                        let from = self.rotated_path(index);
+                        if from.exists() {
+                            fs::rename(from, self.rotated_path(index + 1))?;
+                        }
+                    }
+                    fs::rename(&self.path, self.rotated_path(1))?;
+                }
+                *file = AuditLog::open_file(&self.path)?;
+                Ok(())
+            }
+
+            pub(crate) fn record(&self, record: &AuditRecord) -> io::Result<()> {
+                let line = format!("{}\n", record);
+                let mut file = self.file.lock().unwrap();
+                if file.written > 0 && file.written + line.len() as u64 > self.max_bytes {
+                    self.rotate(&mut file)?;
+                }
+                file.file.write_all(line.as_bytes())?;
+                file.written += line.len() as u64;
+                Ok(())
+            }
+        }
+
+        /// Queue of records written to an `AuditLog` by a background task, so recording never blocks on file IO.
+        /// Records are dropped and counted while the queue is full
+        #[derive(Debug)]
+        pub(crate) struct AuditSink {
+            sender: mpsc::Sender<AuditRecord>,
+            dropped: AtomicUsize,
+        }
+
+        impl AuditSink {
+            /// Start the writer task of `log`, must be called within a tokio runtime
+            pub(crate) fn spawn(log: AuditLog, queue_size: usize) -> AuditSink {
+                let (sender, mut receiver) = mpsc::channel::<AuditRecord>(queue_size.max(1));
+                let log = Arc::new(log);
+                tokio::spawn(async move {
+                    while let Some(record) = receiver.recv().await {
+                        let mut records = vec![record];
+                        while let Ok(record) = receiver.try_recv() {
+                            records.push(record);
+                        }
+                        let log = log.clone();
+                        match tokio::task::spawn_blocking(move || records.iter().try_for_each(|r| log.record(r))).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => error!("failed to write audit record: {:?}", e),
+                            Err(e) => error!("audit log writer failed: {:?}", e),
+                        }
+                    }
+                });
+                AuditSink {
+                    sender,
+                    dropped: AtomicUsize::new(0),
+                }
+            }
+
+            pub(crate) fn record(&self, record: AuditRecord) {
+                match self.sender.try_send(record) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        self.dropped.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Err(TrySendError::Closed(record)) => error!("audit log writer stopped, dropping record: {}", record),
+                }
+            }
+
+            /// Number of records dropped because the queue was full
+            pub(crate) fn dropped(&self) -> usize {
+                self.dropped.load(Ordering::SeqCst)
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use super::{AuditEvent, AuditLog, AuditOutcome, AuditRecord, AuditSink};
+
+            #[test]
+            fn test_audit_record_format() {
+                let mut record = AuditRecord::new(AuditEvent::Delegate, 42, "10.0.0.2:1234", AuditOutcome::Success);
+                record.timestamp_ms = 7;
+                assert!(record.to_string() == "ts=7 event=delegate cap_id=42 peer=10.0.0.2:1234 outcome=success");
+            }
+
+            #[test]
+            fn test_audit_log_rotation() {
+                let dir = std::env::temp_dir().join(format!("tcap-audit-{}", std::process::id()));
+                let _ = std::fs::remove_dir_all(&dir);
+                std::fs::create_dir_all(&dir).unwrap();
+                let path = dir.join("audit.log");
+
+                let record = AuditRecord::new(AuditEvent::Invoke, 1, "10.0.0.2:1234", AuditOutcome::Rejected);
+                let line_size = record.to_string().len() as u64 + 1;
+                let log = AuditLog::open(path.to_str().unwrap(), line_size * 2, 2).unwrap();
+                for _ in 0..7 {
+                    log.record(&record).unwrap();
+                }
+
+                let lines = |p: &std::path::Path| std::fs::read_to_string(p).unwrap().lines().count();
+                assert!(lines(&path) == 1, "current file must hold the records after the last rotation");
+                assert!(lines(&log.rotated_path(1)) == 2);
+                assert!(lines(&log.rotated_path(2)) == 2);
+                assert!(!log.rotated_path(3).exists(), "only the configured number of rotated files is kept");
+
+                std::fs::remove_dir_all(&dir).unwrap();
+            }
+
+            #[tokio::test]
+            async fn test_audit_sink_drops_records_when_full() {
+                let dir = std::env::temp_dir().join(format!("tcap-audit-sink-{}", std::process::id()));
+                let _ = std::fs::remove_dir_all(&dir);
+                std::fs::create_dir_all(&dir).unwrap();
+                let path = dir.join("audit.log");
+
+                // the writer task does not run before the test yields, so only the first record fits into the queue
+                let sink = AuditSink::spawn(AuditLog::open(path.to_str().unwrap(), u64::MAX, 0).unwrap(), 1);
+                for _ in 0..3 {
+                    sink.record(AuditRecord::new(AuditEvent::Invoke, 1, "10.0.0.2:1234", AuditOutcome::Success));
+                }
+                assert!(sink.dropped() == 2);
+
+                let lines = || std::fs::read_to_string(&path).unwrap().lines().count();
+                for _ in 0..100 {
+                    if lines() == 1 {
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+                assert!(lines() == 1, "queued records must be written");
+
+                std::fs::remove_dir_all(&dir).unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+pub mod tcap {
+    pub mod auth {
+        use core::fmt;
+
+        use hmac::{Hmac, Mac};
+        use rand::RngCore;
+        use sha2::Sha256;
+
+        use crate::{capabilities::tcap::{CapID, Rights}, packet_types::tcap::IpAddress};
+
+        /// Decode a non-empty string of hex digits
+        pub(crate) fn decode_hex(val: &str) -> Result<Vec<u8>, String> {
+            if val.is_empty() || !val.is_ascii() || !val.len().is_multiple_of(2) {
+                return Err(format!("expected a non-empty even number of hex digits, got {:?} digits", val.len()));
+            }
+            (0..val.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&val[i..i + 2], 16).map_err(|e| e.to_string()))
+                .collect()
+        }
+
+        /// Encode bytes as lower case hex digits
+        pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+
+        /// Truncated HMAC-SHA256 authenticating a delegated capability, the rights granted with it and the owner epoch
+        pub type CapMac = [u8; 16];
+
+        fn invocation_hmac(cap_mac: &CapMac, header: &[u8]) -> Hmac<Sha256> {
+            let mut mac = Hmac::<Sha256>::new_from_slice(cap_mac).expect("HMAC accepts keys of any size");
+            mac.update(header);
+            mac
+        }
+
+        /// MAC of a single invocation, keyed with the capability MAC and computed over the invocation header.
+        /// Binds the sequence number and timestamp of the invocation to the capability without sending the capability MAC
+        pub(crate) fn invocation_mac(cap_mac: &CapMac, header: &[u8]) -> CapMac {
+            let tag = invocation_hmac(cap_mac, header).finalize().into_bytes();
+            let mut mac: CapMac = [0; 16];
+            mac.copy_from_slice(&tag[..std::mem::size_of::<CapMac>()]);
+            mac
+        }
+
+        /// Constant time check of the MAC presented with an invocation
+        pub(crate) fn verify_invocation_mac(cap_mac: &CapMac, header: &[u8], mac: &CapMac) -> bool {
+            invocation_hmac(cap_mac, header).verify_truncated_left(mac).is_ok()
+        }
+
+        /// Secret key of an owner service, used to authenticate the capabilities it delegates
+        #[derive(Clone)]
+        pub struct MacKey {
+            key: Vec<u8>,
+        }
+
+        impl fmt::Debug for MacKey {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct("MacKey").finish_non_exhaustive()
+            }
+        }
+
+        impl MacKey {
+            pub fn generate() -> MacKey {
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                MacKey { key }
+            }
+
+            pub fn from_hex(val: &str) -> Result<MacKey, String> {
+                let key = decode_hex(val).map_err(|e| format!("invalid MAC key: {}", e))?;
+                Ok(MacKey { key })
+            }
+
+            pub(crate) fn to_hex(&self) -> String {
+                encode_hex(&self.key)
+            }
+
+            fn mac(&self, cap_id: CapID, owner: &IpAddress, rights: Rights, epoch: u32) -> Hmac<Sha256> {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+                mac.update(&cap_id.to_le_bytes());
+                mac.update(&owner.address);
+                mac.update(&owner.port.to_le_bytes());
+                mac.update(&[rights.bits()]);
+                mac.update(&epoch.to_le_bytes());
+                mac
+            }
+
+            pub fn compute(&self, cap_id: CapID, owner: &IpAddress, rights: Rights, epoch: u32) -> CapMac {
+                let tag = self.mac(cap_id, owner, rights, epoch).finalize().into_bytes();
+                let mut mac: CapMac = [0; 16];
+                mac.copy_from_slice(&tag[..std::mem::size_of::<CapMac>()]);
+                mac
+            }
+
+            /// Constant time check of a presented MAC
+            pub fn verify(&self, cap_id: CapID, owner: &IpAddress, rights: Rights, epoch: u32, mac: &CapMac) -> bool {
+                self.mac(cap_id, owner, rights, epoch).verify_truncated_left(mac).is_ok()
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use super::{invocation_mac, verify_invocation_mac, MacKey};
+            use crate::{capabilities::tcap::Rights, packet_types::tcap::IpAddress};
+
+            #[test]
+            fn test_mac_verification() {
+                let key = MacKey::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+                let owner = IpAddress::from("10.0.0.1:1234");
+                let rights = Rights::READ | Rights::DELEGATE;
+                let mac = key.compute(42, &owner, rights, 7);
+
+                assert!(key.verify(42, &owner, rights, 7, &mac), "MAC must verify for the same cap");
+                assert!(!key.verify(43, &owner, rights, 7, &mac), "MAC must not verify for a different cap id");
+                assert!(!key.verify(42, &IpAddress::from("10.0.0.2:1234"), rights, 7, &mac), "MAC must not verify for a different owner");
+                assert!(!key.verify(42, &owner, Rights::all(), 7, &mac), "MAC must not verify for amplified rights");
+                assert!(!key.verify(42, &owner, rights, 8, &mac), "MAC must not verify in a different epoch");
+                assert!(!MacKey::generate().verify(42, &owner, rights, 7, &mac), "MAC must not verify with a different key");
+            }
+
+            #[test]
+            fn test_invocation_mac_verification() {
+                let cap_mac = MacKey::generate().compute(42, &IpAddress::from("10.0.0.1:1234"), Rights::INVOKE, 7);
+                let header = [1u8, 2, 3, 4];
+                let mac = invocation_mac(&cap_mac, &header);
+
+                assert!(verify_invocation_mac(&cap_mac, &header, &mac), "MAC must verify for the same header");
+                assert!(!verify_invocation_mac(&cap_mac, &[1, 2, 3, 5], &mac), "MAC must not verify for a modified header");
+                assert!(!verify_invocation_mac(&[0; 16], &header, &mac), "MAC must not verify without the capability MAC");
+            }
+
+            #[test]
+            fn test_mac_key_from_hex() {
+                assert!(MacKey::from_hex("0a0B").is_ok());
+                assert!(MacKey::from_hex("").is_err());
+                assert!(MacKey::from_hex("abc").is_err());
+                assert!(MacKey::from_hex("zz").is_err());
+            }
+        }
+    }
+}
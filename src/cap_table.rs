@@ -1,52 +1,231 @@
 pub mod tcap {
     pub(crate) mod cap_table {
-        use std::{collections::HashMap, sync::Arc};
+        use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc, RwLock}};
 
         use log::debug;
-        use tokio::sync::{Mutex, RwLock};
+        use tokio::sync::Mutex;
+
+        use crate::{capabilities::tcap::{Capability, CapID, CapType}, packet_types::tcap::IpAddress};
+
+        /// Fields of a capability readable without locking the capability.
+        /// The type changes when an object is bound, see `CapTable::refresh`
+        #[derive(Debug, Clone, Copy)]
+        pub(crate) struct CapMeta {
+            pub(crate) cap_id: CapID,
+            pub(crate) owner_address: IpAddress,
+            pub(crate) owned: bool,
+            pub(crate) cap_type: CapType,
+        }
+
+        impl CapMeta {
+            fn of(cap: &Capability) -> CapMeta {
+                CapMeta {
+                    cap_id: cap.cap_id,
+                    owner_address: cap.owner_address(),
+                    owned: cap.is_owned(),
+                    cap_type: cap.cap_type,
+                }
+            }
+        }
 
-        use crate::capabilities::tcap::{Capability, CapID};
+        #[derive(Debug, Clone)]
+        struct CapEntry {
+            meta: CapMeta,
+            /// copy of the capability as of its last change, shares the bound objects, holders and derivations
+            view: Arc<Capability>,
+            cap: Arc<Mutex<Capability>>,
+        }
+
+        type Shard = RwLock<HashMap<CapID, CapEntry>>;
+
+        /// Capabilities held by a service, split into shards by cap id.
+        /// Lookups only lock their shard and never wait on a capability, locks are never held across an await
         #[derive(Debug, Clone)]
         pub(crate) struct CapTable {
-            caps: Arc<RwLock<HashMap<CapID, Arc<Mutex<Capability>>>>>,
+            shards: Arc<Vec<Shard>>,
+            owned: Arc<AtomicUsize>,
+            received: Arc<AtomicUsize>,
         }
 
         impl CapTable {
-            pub(crate) async fn new() -> Self {
-                let caps = Arc::new(RwLock::new(HashMap::new()));
+            pub(crate) async fn new(shards: usize) -> Self {
+                let shards = Arc::new((0..shards.max(1)).map(|_| RwLock::new(HashMap::new())).collect());
+
+                Self { shards, owned: Arc::new(AtomicUsize::new(0)), received: Arc::new(AtomicUsize::new(0)) }
+            }
 
-                Self { caps }
+            fn shard(&self, cap_id: CapID) -> &Shard {
+                // cap ids are random, predefined ones are usually consecutive, both spread over the low bits
+                &self.shards[(cap_id % self.shards.len() as u128) as usize]
+            }
+
+            /// Counter of the kind of caps `meta` describes
+            fn counter(&self, meta: &CapMeta) -> &AtomicUsize {
+                if meta.owned { &self.owned } else { &self.received }
             }
 
             pub(crate) async fn reset(&self) {
-                self.caps.write().await.clear();
+                for shard in self.shards.iter() {
+                    shard.write().unwrap().clear();
+                }
+                self.owned.store(0, Ordering::SeqCst);
+                self.received.store(0, Ordering::SeqCst);
                 debug!("Reset Cap Table");
             }
 
             pub(crate) async fn insert(&self, cap: Arc<Mutex<Capability>>) {
-                let id = cap.lock().await.cap_id;
-                self.caps.write().await.insert(id, cap);
+                self.try_insert(cap, None).await;
+            }
+
+            /// Insert `cap` unless `limit` caps of its kind, owned or received, are held. Returns false if the table is full.
+            /// The cap is counted under the shard lock, concurrent inserts cannot exceed the limit.
+            /// Replacing a held copy of the cap needs no room
+            pub(crate) async fn try_insert(&self, cap: Arc<Mutex<Capability>>, limit: Option<usize>) -> bool {
+                let view = Arc::new(cap.lock().await.clone());
+                let meta = CapMeta::of(&view);
+                let id = meta.cap_id;
+                let mut shard = self.shard(id).write().unwrap();
+                let replaces_same_kind = shard.get(&id).is_some_and(|held| held.meta.owned == meta.owned);
+                if !replaces_same_kind {
+                    let reserved = self.counter(&meta).fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| match limit {
+                        Some(limit) if count >= limit => None,
+                        _ => Some(count + 1),
+                    });
+                    if reserved.is_err() {
+                        debug!("capID {:?} not inserted, table holds {:?} caps of its kind", id, limit);
+                        return false;
+                    }
+                }
+                if let Some(replaced) = shard.insert(id, CapEntry { meta, view, cap }) {
+                    if !replaces_same_kind {
+                        self.counter(&replaced.meta).fetch_sub(1, Ordering::SeqCst);
+                    }
+                }
                 debug!("Inserted capID {:?} into table", id);
+                true
+            }
+
+            /// Update the metadata and view of an inserted capability after it changed
+            pub(crate) fn refresh(&self, cap: &Capability) {
+                if let Some(entry) = self.shard(cap.cap_id).write().unwrap().get_mut(&cap.cap_id) {
+                    entry.meta = CapMeta::of(cap);
+                    entry.view = Arc::new(cap.clone());
+                }
             }
 
             pub(crate) async fn remove(&self, cap_id: CapID) {
-                self.caps.write().await.remove(&cap_id);
+                if let Some(removed) = self.shard(cap_id).write().unwrap().remove(&cap_id) {
+                    self.counter(&removed.meta).fetch_sub(1, Ordering::SeqCst);
+                }
                 debug!("Removed capID {:?} from table", cap_id);
             }
 
-            pub(crate) async fn get_capids(&self) -> Vec<CapID> {
-                self.caps.read().await.keys().cloned().collect()
+            pub(crate) async fn get_caps(&self) -> Vec<Arc<Mutex<Capability>>> {
+                self.shards
+                    .iter()
+                    .flat_map(|shard| shard.read().unwrap().values().map(|entry| entry.cap.clone()).collect::<Vec<_>>())
+                    .collect()
+            }
+
+            /// All capabilities together with their metadata, filtering on the metadata does not lock any capability
+            pub(crate) async fn get_caps_with_meta(&self) -> Vec<(CapMeta, Arc<Mutex<Capability>>)> {
+                self.shards
+                    .iter()
+                    .flat_map(|shard| shard.read().unwrap().values().map(|entry| (entry.meta, entry.cap.clone())).collect::<Vec<_>>())
+                    .collect()
+            }
+
+            /// Number of caps owned by this service
+            pub(crate) fn owned_count(&self) -> usize {
+                self.owned.load(Ordering::SeqCst)
+            }
+
+            /// Number of caps owned by other services
+            pub(crate) fn received_count(&self) -> usize {
+                self.received.load(Ordering::SeqCst)
             }
 
             pub(crate) async fn contains(&self, cap_id: CapID) -> bool {
-                self.caps.read().await.contains_key(&cap_id)
+                self.shard(cap_id).read().unwrap().contains_key(&cap_id)
             }
 
             pub(crate) async fn get(&self, id: CapID) -> Option<Arc<Mutex<Capability>>> {
-                match self.caps.read().await.get(&id) {
-                    Some(cap) => Some(cap.clone()),
-                    None => None,
+                self.shard(id).read().unwrap().get(&id).map(|entry| entry.cap.clone())
+            }
+
+            /// Capability with `id` together with its metadata, a single lookup for the packet paths
+            pub(crate) async fn get_with_meta(&self, id: CapID) -> Option<(CapMeta, Arc<Mutex<Capability>>)> {
+                self.shard(id).read().unwrap().get(&id).map(|entry| (entry.meta, entry.cap.clone()))
+            }
+
+            /// Metadata and view of the capability with `id` for the invocation paths, neither locks the capability.
+            /// The view is replaced whenever the capability changes, see `CapTable::refresh`
+            pub(crate) async fn get_view(&self, id: CapID) -> Option<(CapMeta, Arc<Capability>)> {
+                self.shard(id).read().unwrap().get(&id).map(|entry| (entry.meta, entry.view.clone()))
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports, dead_code)]
+            use std::sync::Arc;
+
+            use tokio::sync::Mutex;
+
+            use super::CapTable;
+            use crate::{capabilities::tcap::{CapID, Capability}, packet_types::tcap::InsertCapHeader};
+
+            /// Cap received from another service, caps without a service are never owned
+            fn received(cap_id: CapID) -> Arc<Mutex<Capability>> {
+                let mut hdr: InsertCapHeader = bytemuck::Zeroable::zeroed();
+                hdr.cap_id = cap_id;
+                Arc::new(Mutex::new(Capability::from(hdr)))
+            }
+
+            #[tokio::test]
+            async fn test_counters() {
+                let table = CapTable::new(4).await;
+                for cap_id in 1..=3 {
+                    table.insert(received(cap_id)).await;
+                }
+                assert!(table.received_count() == 3);
+                assert!(table.owned_count() == 0);
+
+                table.insert(received(2)).await;
+                assert!(table.received_count() == 3, "replacing a cap does not count it twice");
+                table.remove(2).await;
+                table.remove(2).await;
+                assert!(table.received_count() == 2, "removing a missing cap does not change the count");
+                table.reset().await;
+                assert!(table.received_count() == 0);
+            }
+
+            #[tokio::test]
+            async fn test_try_insert_respects_limit() {
+                let table = CapTable::new(4).await;
+                assert!(table.try_insert(received(1), Some(2)).await);
+                assert!(table.try_insert(received(2), Some(2)).await);
+                assert!(!table.try_insert(received(3), Some(2)).await);
+                assert!(!table.contains(3).await);
+                assert!(table.try_insert(received(2), Some(2)).await, "replacing a held cap needs no room");
+                assert!(table.try_insert(received(3), None).await);
+                assert!(table.received_count() == 3);
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+            async fn test_concurrent_try_insert() {
+                let table = CapTable::new(16).await;
+                let mut inserts = Vec::new();
+                for cap_id in 0..256 {
+                    let table = table.clone();
+                    inserts.push(tokio::spawn(async move { table.try_insert(received(cap_id), Some(100)).await }));
+                }
+                let mut inserted = 0;
+                for insert in inserts {
+                    inserted += insert.await.unwrap() as usize;
                 }
+                assert!(inserted == 100);
+                assert!(table.received_count() == 100);
+                assert!(table.get_caps().await.len() == 100);
             }
         }
     }
@@ -1,13 +1,19 @@
 pub mod tcap {
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
     use crate::{
+        MEMCOPY_ACK_TIMEOUT_MS, MEMCOPY_MAX_RETRANSMITS,
+        audit::tcap::audit::{AuditEvent, AuditOutcome},
+        auth::tcap::auth::CapMac,
         object::tcap::object::{RequestObject, MemoryObject},
+        snapshot::tcap::snapshot::CapSnapshot,
         packet_types::tcap::{
-            CmdType, Flags, InsertCapHeader, IpAddress, MemoryCopyRequestHeader, MemoryCopyResponseHeader, RequestInvokeHeader, RequestResponseHeader, RevokeCapHeader
+            CapTableFullHeader, DelegationNoticeHeader, Flags, InsertCapHeader, IpAddress, LeaseRenewHeader, LeaseRenewResponseHeader, MemoryCopyRequestHeader, MemoryCopyResponse, RequestInvokeHeader, RequestResponseHeader, RevokeAckHeader, RevokeCapHeader, StaleEpochHeader, RESPONSE_CAP_TABLE_FULL, RESPONSE_FAILED, RESPONSE_OK
         },
+        replay::tcap::replay::now_ms,
         service::tcap::{SendRequest, Service},
     };
+    use bitflags::bitflags;
     use log::*;
     use rand::Rng;
     use tokio::sync::Mutex;
@@ -22,6 +28,32 @@ pub mod tcap {
 
     pub type CapID = u128;
 
+    /// Delegation depth of capabilities that may be delegated any number of times
+    pub const UNLIMITED_DELEGATION_DEPTH: u8 = u8::MAX;
+
+    /// Whether a delegation from a node with `delegator_depth` may hand out `delegation_depth`
+    pub(crate) fn is_valid_delegation_depth(delegator_depth: u8, delegation_depth: u8) -> bool {
+        match delegator_depth {
+            0 => false,
+            UNLIMITED_DELEGATION_DEPTH => true,
+            _ => delegation_depth < delegator_depth,
+        }
+    }
+
+    bitflags! {
+        /// Operations the holder of a capability may perform
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub struct Rights: u8 {
+            /// copy the memory object
+            const READ = 1;
+            /// reserved for writes to memory objects
+            const WRITE = 2;
+            const INVOKE = 4;
+            const DELEGATE = 8;
+            const REVOKE = 16;
+        }
+    }
+
     impl From<u8> for CapType {
         fn from(value: u8) -> Self {
             match value {
@@ -43,14 +75,83 @@ pub mod tcap {
         }
     }
 
+    /// Epoch of capabilities whose owner epoch is unknown, e.g. remote caps created from a predefined id.
+    /// Owners do not check the epoch of such caps
+    pub const UNKNOWN_EPOCH: u32 = 0;
+
+    /// Reasons an invocation of a capability fails
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum InvokeError {
+        /// the local copy lacks the right the operation requires
+        PermissionDenied,
+        /// the owner does not know the capability or refused the invocation
+        Invalid,
+        /// the capability was minted before the owner was reset or restarted
+        StaleEpoch,
+        /// the handler ran and reported an error
+        Failed,
+        /// the owner did not answer or a transfer stopped before it was complete
+        Timeout,
+    }
+
+    /// Delegation of a capability from `delegator` to `delegatee`, an edge of the derivation tree
+    #[derive(Clone, Copy, Debug)]
+    pub struct Derivation {
+        pub delegator: IpAddress,
+        pub delegatee: IpAddress,
+        /// delegation depth granted to the delegatee
+        pub depth: u8,
+    }
+
+    /// `root` and all nodes reachable from it in `derivations`, each node once even if delegations form a cycle
+    fn subtree(derivations: &[Derivation], root: IpAddress) -> Vec<IpAddress> {
+        let mut nodes = vec![root];
+        let mut next = 0;
+        while next < nodes.len() {
+            let node = nodes[next];
+            for d in derivations.iter().filter(|d| d.delegator.same_node(&node)) {
+                if !nodes.iter().any(|n| n.same_node(&d.delegatee)) {
+                    nodes.push(d.delegatee);
+                }
+            }
+            next += 1;
+        }
+        nodes
+    }
+
+    /// Outcome of a revocation, listing the addresses of the nodes the revocation was sent to
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct RevocationReport {
+        /// nodes that acknowledged the revocation
+        pub confirmed: Vec<String>,
+        /// nodes that did not acknowledge the revocation after all retries
+        pub timed_out: Vec<String>,
+    }
+
     #[derive(Clone, Debug)]
     pub struct Capability {
         pub cap_id: CapID,
         pub cap_type: CapType,
         owner_address: IpAddress,
+        /// node that delegated the capability to this service, the owner for owned and self-created caps
+        delegator: IpAddress,
         delegatees: Arc<Mutex<Vec<IpAddress>>>,
+        /// all delegations of the capability, only maintained by the owner
+        derivations: Arc<Mutex<Vec<Derivation>>>,
         request_object: Option<Arc<Mutex<RequestObject>>>,
         memory_object: Option<Arc<Mutex<MemoryObject>>>,
+        /// rights of the holder, never more than `granted_rights`
+        rights: Rights,
+        /// rights the owner granted and authenticated with `mac`
+        granted_rights: Rights,
+        /// number of further delegations, zero for non-transferable caps
+        delegation_depth: u8,
+        /// unix time in ms at which the owner revokes the capability, zero if it is not leased
+        lease_expiry_ms: u64,
+        /// epoch of the owner the capability was minted in
+        epoch: u32,
+        /// MAC issued by the owner, presented on invocations of delegated capabilities
+        mac: CapMac,
         pub service: Option<Arc<Service>>
     }
 
@@ -60,9 +161,17 @@ pub mod tcap {
                 cap_id: value.cap_id,
                 cap_type: CapType::from(value.cap_type),
                 owner_address: IpAddress{ address: value.object_owner_ip_address, netmask: [0,0,0,0], port: value.object_owner_port},
+                delegator: IpAddress{ address: value.delegator_ip_address, netmask: [0,0,0,0], port: value.delegator_port},
                 delegatees: Arc::new(Mutex::new(Vec::new())),
+                derivations: Arc::new(Mutex::new(Vec::new())),
                 request_object: None,
                 memory_object: None,
+                rights: Rights::from_bits_truncate(value.rights),
+                granted_rights: Rights::from_bits_truncate(value.granted_rights),
+                delegation_depth: value.delegation_depth,
+                lease_expiry_ms: value.lease_expiry_ms,
+                epoch: value.epoch,
+                mac: value.mac,
                 service: None
             }
         }
@@ -79,28 +188,44 @@ pub mod tcap {
             let mut rng = rand::thread_rng();
             let cap_id = rng.gen::<CapID>();
 
-            let owner_address = IpAddress::from(s.config.address.as_str());
+            let owner_address = IpAddress::from(s.config.advertised_address());
 
             Capability {
                 cap_id,
                 cap_type: CapType::None,
                 owner_address,
+                delegator: owner_address,
                 delegatees: Arc::new(Mutex::new(Vec::new())),
+                derivations: Arc::new(Mutex::new(Vec::new())),
                 request_object: None,
                 memory_object: None,
+                rights: Rights::all(),
+                granted_rights: Rights::all(),
+                delegation_depth: UNLIMITED_DELEGATION_DEPTH,
+                lease_expiry_ms: 0,
+                epoch: s.epoch(),
+                mac: [0; 16],
                 service: Some(s)
             }
         }
 
         pub(crate) async fn create_with_id(s: Arc<Service>, cap_id: CapID) -> Capability {
-            let owner_address = IpAddress::from(s.config.address.as_str());
+            let owner_address = IpAddress::from(s.config.advertised_address());
             Capability {
                 cap_id,
                 cap_type: CapType::None,
                 owner_address,
+                delegator: owner_address,
                 delegatees: Arc::new(Mutex::new(Vec::new())),
+                derivations: Arc::new(Mutex::new(Vec::new())),
                 request_object: None,
                 memory_object: None,
+                rights: Rights::all(),
+                granted_rights: Rights::all(),
+                delegation_depth: UNLIMITED_DELEGATION_DEPTH,
+                lease_expiry_ms: 0,
+                epoch: s.epoch(),
+                mac: [0; 16],
                 service: Some(s)
             }
         }
@@ -110,13 +235,197 @@ pub mod tcap {
                 cap_id,
                 cap_type: CapType::None,
                 owner_address,
+                delegator: owner_address,
                 delegatees: Arc::new(Mutex::new(Vec::new())),
+                derivations: Arc::new(Mutex::new(Vec::new())),
                 request_object: None,
                 memory_object: None,
+                rights: Rights::all(),
+                granted_rights: Rights::all(),
+                delegation_depth: UNLIMITED_DELEGATION_DEPTH,
+                lease_expiry_ms: 0,
+                epoch: UNKNOWN_EPOCH,
+                mac: [0; 16],
                 service: Some(s)
             }
         }
 
+        /// Set the MAC the owner issued in `epoch`
+        pub(crate) fn set_mac(&mut self, mac: CapMac, epoch: u32) {
+            self.mac = mac;
+            self.epoch = epoch;
+            self.refresh_table_entry();
+        }
+
+        /// Address of the service owning the capability object
+        pub fn owner_address(&self) -> IpAddress {
+            self.owner_address
+        }
+
+        /// Nodes the local copy of the capability was delegated to
+        pub async fn delegatees(&self) -> Vec<IpAddress> {
+            self.delegatees.lock().await.clone()
+        }
+
+        /// Whether the local service is the owner of the capability object
+        pub fn is_owned(&self) -> bool {
+            match self.service.as_ref() {
+                Some(s) => {
+                    let local = IpAddress::from(s.config.advertised_address());
+                    local.address == self.owner_address.address && local.port == self.owner_address.port
+                }
+                None => false,
+            }
+        }
+
+        /// Whether `source` may revoke the local copy, only the owner and the node that delegated it may
+        pub(crate) fn may_revoke(&self, source: &IpAddress) -> bool {
+            self.owner_address.same_node(source) || self.delegator.same_node(source)
+        }
+
+        /// Whether `source` is the owner or holds a delegation of the capability
+        pub(crate) async fn is_authorized(&self, source: &IpAddress) -> bool {
+            self.owner_address.same_node(source)
+                || self.delegatees.lock().await.iter().any(|d| d.same_node(source))
+                || self.derivations.lock().await.iter().any(|d| d.delegatee.same_node(source))
+        }
+
+        /// Nodes holding a copy of the capability, as far as known to the local copy.
+        /// The owner knows all holders, other nodes only their own delegatees
+        pub async fn holders(&self) -> Vec<IpAddress> {
+            let mut holders = self.delegatees.lock().await.clone();
+            for derivation in self.derivations.lock().await.iter() {
+                if !holders.iter().any(|h| h.same_node(&derivation.delegatee)) {
+                    holders.push(derivation.delegatee);
+                }
+            }
+            holders
+        }
+
+        /// Forget a holder that closed its copy, false if it was not a holder.
+        /// Copies derived from the closed one stay holders
+        pub(crate) async fn remove_holder(&self, holder: &IpAddress) -> bool {
+            let mut delegatees = self.delegatees.lock().await;
+            let mut derivations = self.derivations.lock().await;
+            let known = delegatees.iter().any(|d| d.same_node(holder)) || derivations.iter().any(|d| d.delegatee.same_node(holder));
+            delegatees.retain(|d| !d.same_node(holder));
+            derivations.retain(|d| !d.delegatee.same_node(holder));
+            known
+        }
+
+        /// Drop the bound objects, used when the owner reclaims a capability without holders
+        pub(crate) fn release_objects(&mut self) {
+            self.request_object = None;
+            self.memory_object = None;
+            self.refresh_table_entry();
+        }
+
+        /// Delegations of the capability known to the owner, empty on other nodes
+        pub async fn derivation_tree(&self) -> Vec<Derivation> {
+            self.derivations.lock().await.clone()
+        }
+
+        /// Record a delegation reported by a holder and return the depth the owner granted to the delegator.
+        /// None if `delegator` does not hold the capability or its depth does not allow handing out `depth`
+        pub(crate) async fn record_derivation(&self, delegator: IpAddress, delegatee: IpAddress, depth: u8) -> Option<u8> {
+            let mut derivations = self.derivations.lock().await;
+            // the delegator's own report of its depth is not trusted, the owner knows what it granted
+            let delegator_depth = match self.owner_address.same_node(&delegator) {
+                true => UNLIMITED_DELEGATION_DEPTH,
+                false => derivations.iter().filter(|d| d.delegatee.same_node(&delegator)).map(|d| d.depth).max()?,
+            };
+            if !is_valid_delegation_depth(delegator_depth, depth) {
+                return None;
+            }
+            derivations.push(Derivation { delegator, delegatee, depth });
+            Some(delegator_depth)
+        }
+
+        /// `root` and all nodes that received the capability from it directly or indirectly
+        async fn subtree(&self, root: IpAddress) -> Vec<IpAddress> {
+            subtree(&self.derivations.lock().await, root)
+        }
+
+        pub fn rights(&self) -> Rights {
+            self.rights
+        }
+
+        /// Rights granted by the owner and covered by the MAC, a superset of `rights`
+        pub(crate) fn granted_rights(&self) -> Rights {
+            self.granted_rights
+        }
+
+        /// Number of further delegations, `UNLIMITED_DELEGATION_DEPTH` if unrestricted
+        pub fn delegation_depth(&self) -> u8 {
+            self.delegation_depth
+        }
+
+        /// Epoch of the owner the capability was minted in, `UNKNOWN_EPOCH` if unknown
+        pub fn epoch(&self) -> u32 {
+            self.epoch
+        }
+
+        /// Unix time in ms at which the owner revokes the capability, if it is leased
+        pub fn lease_expiry_ms(&self) -> Option<u64> {
+            match self.lease_expiry_ms {
+                0 => None,
+                expiry => Some(expiry),
+            }
+        }
+
+        /// Depth of delegations that do not specify one, one less than the own depth
+        fn default_delegation_depth(&self) -> u8 {
+            match self.delegation_depth {
+                UNLIMITED_DELEGATION_DEPTH => UNLIMITED_DELEGATION_DEPTH,
+                depth => depth.saturating_sub(1),
+            }
+        }
+
+        /// MAC to present to the owner, owners compute it from their key
+        pub(crate) fn mac(&self) -> CapMac {
+            match self.service.as_ref() {
+                Some(s) if self.is_owned() => s.capability_mac(self.cap_id),
+                _ => self.mac,
+            }
+        }
+
+        /// Persisted state of an owned capability
+        pub(crate) async fn snapshot(&self) -> CapSnapshot {
+            let handler = match self.request_object.as_ref() {
+                Some(o) => o.lock().await.name().map(|name| name.to_string()),
+                None => None,
+            };
+            let memory = match self.memory_object.as_ref() {
+                Some(o) => Some(o.lock().await.data()),
+                None => None,
+            };
+            CapSnapshot {
+                cap_id: self.cap_id,
+                cap_type: self.cap_type,
+                handler,
+                memory,
+                delegatees: self.delegatees.lock().await.clone(),
+                derivations: self.derivations.lock().await.clone(),
+            }
+        }
+
+        /// Restore the delegations of a capability from a snapshot
+        pub(crate) async fn restore_delegations(&self, snapshot: &CapSnapshot) {
+            *self.delegatees.lock().await = snapshot.delegatees.clone();
+            *self.derivations.lock().await = snapshot.derivations.clone();
+        }
+
+        /// Bind the handler registered as `name` with `Service::register_handler`.
+        /// Caps bound by name are rebound when restored from a snapshot
+        pub async fn bind_handler(&mut self, name: &str) -> tokio::io::Result<()> {
+            let handler = self.service.as_ref().unwrap().handler(name).await.ok_or(tokio::io::Error::new(
+                tokio::io::ErrorKind::NotFound,
+                format!("no handler registered as {:?}", name),
+            ))?;
+            self.bind_req(Arc::new(Mutex::new(RequestObject::named(name, handler).await))).await;
+            Ok(())
+        }
+
         #[deprecated = "Memory objects are supported, `bind_req` should now be used for request objects"]
         pub async fn bind(&mut self, obj: Arc<Mutex<RequestObject>>) {
             self.bind_req(obj).await;
@@ -131,6 +440,7 @@ pub mod tcap {
                 .await
                 .set_cap(self.clone());
             self.cap_type = CapType::Request;
+            self.refresh_table_entry();
             debug!("Binding obj {:?} to cap {:?}", self.request_object, self.cap_id);
         }
 
@@ -143,124 +453,433 @@ pub mod tcap {
                 .await
                 .set_cap(self.clone());
             self.cap_type = CapType::Memory;
+            self.refresh_table_entry();
             debug!("Binding obj {:?} to cap {:?}", self.memory_object, self.cap_id);
         }
 
+        /// Publish a change of the capability to the lock-free view of the cap table.
+        /// Every method changing a field of the capability calls it, holders and derivations are shared with the view
+        fn refresh_table_entry(&self) {
+            if let Some(service) = self.service.as_ref() {
+                service.cap_table.refresh(self);
+            }
+        }
+
         pub async fn delegate(
             &self,
             delegatee: IpAddress,
         ) -> Result<(), tokio::io::Error> {
+            self.delegate_with_rights(delegatee, self.rights).await
+        }
+
+        /**
+         * Delegate the capability with a subset of the own rights
+         */
+        pub async fn delegate_with_rights(
+            &self,
+            delegatee: IpAddress,
+            rights: Rights,
+        ) -> Result<(), tokio::io::Error> {
+            self.delegate_with_depth(delegatee, rights, self.default_delegation_depth()).await
+        }
+
+        /**
+         * Delegate the capability with a subset of the own rights, allowing the delegatee `depth` further delegations.
+         * A depth of zero makes the delegated capability non-transferable.
+         */
+        pub async fn delegate_with_depth(
+            &self,
+            delegatee: IpAddress,
+            rights: Rights,
+            depth: u8,
+        ) -> Result<(), tokio::io::Error> {
+            // further delegations of leased caps end with the lease, as the owner's revocation cascades
+            self.delegate_checked(delegatee, rights, depth, self.lease_expiry_ms).await
+        }
+
+        /**
+         * Delegate the capability for `duration`, the owner revokes the delegation when the lease expires
+         * unless the delegatee renews it with `renew_lease`.
+         * Only the owner can hand out leases.
+         */
+        pub async fn delegate_with_lease(
+            &self,
+            delegatee: IpAddress,
+            duration: Duration,
+        ) -> Result<(), tokio::io::Error> {
+            if !self.is_owned() {
+                return Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::PermissionDenied,
+                    format!("cap {:?} is not owned by this service and cannot be leased", self.cap_id),
+                ));
+            }
+            let duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+            let lease_expiry_ms = now_ms().saturating_add(duration_ms);
+            self.delegate_checked(delegatee, self.rights, self.default_delegation_depth(), lease_expiry_ms).await?;
+            self.service.as_ref().unwrap().grant_lease(self.cap_id, delegatee.into(), lease_expiry_ms, duration_ms).await;
+            Ok(())
+        }
+
+        async fn delegate_checked(
+            &self,
+            delegatee: IpAddress,
+            rights: Rights,
+            depth: u8,
+            lease_expiry_ms: u64,
+        ) -> Result<(), tokio::io::Error> {
+            if !self.rights.contains(Rights::DELEGATE) || !self.rights.contains(rights) {
+                return Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::PermissionDenied,
+                    format!("cap {:?} with rights {:?} cannot be delegated with rights {:?}", self.cap_id, self.rights, rights),
+                ));
+            }
+            if !is_valid_delegation_depth(self.delegation_depth, depth) {
+                return Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::PermissionDenied,
+                    format!("cap {:?} with delegation depth {:?} cannot be delegated with depth {:?}", self.cap_id, self.delegation_depth, depth),
+                ));
+            }
+            let service = self.service.as_ref().unwrap();
+            let dest: String = delegatee.into();
+            if !self.is_owned() {
+                // only the owner can authenticate the narrowed rights, it inserts the cap at the delegatee
+                self.request_delegation(service, delegatee, rights, depth).await?;
+                self.delegatees.lock().await.push(delegatee);
+                service.audit(AuditEvent::Delegate, self.cap_id, &dest, AuditOutcome::Success);
+                return Ok(());
+            }
+
             self.delegatees.lock().await.push(delegatee);
-            let address = self.service.as_ref().unwrap().config.address.clone();
-            let packet: Box<[u8; std::mem::size_of::<InsertCapHeader>()]> =
-                InsertCapHeader::construct(&self, delegatee, IpAddress::from(address.as_str()))
-                    .into();
+            // the owner tracks the derivation tree to revoke all downstream copies
+            let derivation = Derivation { delegator: self.owner_address, delegatee, depth };
+            self.derivations.lock().await.push(derivation);
+            self.insert_at(service, derivation, rights, self.delegation_depth, lease_expiry_ms).await?;
+            service.audit(AuditEvent::Delegate, self.cap_id, &dest, AuditOutcome::Success);
+
+            Ok(())
+        }
+
+        /// Ask the owner to delegate the capability to `delegatee` on behalf of this holder and wait for its answer
+        async fn request_delegation(&self, s: &Service, delegatee: IpAddress, rights: Rights, depth: u8) -> Result<(), tokio::io::Error> {
+            let owner: String = self.owner_address.into();
+            let stream_id = s.open_stream(&owner).await;
+            let packet: Box<[u8; std::mem::size_of::<DelegationNoticeHeader>()]> =
+                DelegationNoticeHeader::construct(self, stream_id, delegatee, rights, self.granted_rights, depth, self.mac).into();
+
+            if let Some(notifier) = s.send(SendRequest::new(owner.clone(), packet), true).await {
+                // the owner waits for the delegatee to accept the cap before answering
+                let timeout = Duration::from_millis(2 * s.config.response_timeout_ms);
+                if let Ok(permit) = tokio::time::timeout(timeout, notifier.acquire()).await {
+                    permit.unwrap().forget();
+                }
+            }
+            let resp = s.get_response(&owner, stream_id, 0).await;
+            s.close_stream(&owner, stream_id).await;
+            match resp.map(|resp| RequestResponseHeader::try_from(&resp.data[..])) {
+                Some(Ok(resp)) if resp.response_code == RESPONSE_OK => Ok(()),
+                Some(Ok(resp)) if resp.response_code == RESPONSE_CAP_TABLE_FULL => Err(tokio::io::Error::other(
+                    format!("cap table full, {:?} rejected the delegation of cap {:?}", delegatee, self.cap_id),
+                )),
+                Some(_) => Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::PermissionDenied,
+                    format!("owner {:?} refused the delegation of cap {:?} to {:?}", owner, self.cap_id, delegatee),
+                )),
+                None => Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::TimedOut,
+                    format!("owner {:?} did not answer the delegation of cap {:?}", owner, self.cap_id),
+                )),
+            }
+        }
+
+        /// Insert the owned capability at the delegatee of `derivation` with a MAC for exactly `rights`, on the switch and the delegatee.
+        /// Waits for the delegatee to accept the cap, rejected delegations are removed from the derivation tree again.
+        /// Delegations the delegatee did not answer stay recorded, so revocations still reach it
+        pub(crate) async fn insert_at(&self, s: &Service, derivation: Derivation, rights: Rights, delegator_depth: u8, lease_expiry_ms: u64) -> tokio::io::Result<()> {
+            let delegatee = derivation.delegatee;
+            let dest: String = delegatee.into();
+            let stream_id = s.open_stream(&dest).await;
+            let mac = s.capability_mac_with_rights(self.cap_id, rights);
+            let mut header = InsertCapHeader::construct(self, &derivation, rights, delegator_depth, lease_expiry_ms, mac);
+            header.common.stream_id = stream_id;
+            let packet: Box<[u8; std::mem::size_of::<InsertCapHeader>()]> = header.into();
             debug!("packet to be send: {:?}", packet);
 
             #[cfg(feature="directCPcommunication")]
             {
-                let ctrl_plane = self.service.as_ref().unwrap().config.switch_addr.clone();
-                let _ = self.service.as_ref().unwrap().send(SendRequest::new(ctrl_plane, packet.clone()), false).await;    
+                let _ = s.send(SendRequest::new(s.config.switch_addr.clone(), packet.clone()), false).await;
+            }
+
+            if let Some(notifier) = s.send(SendRequest::new(dest.clone(), packet), true).await {
+                let timeout = Duration::from_millis(s.config.response_timeout_ms);
+                if let Ok(permit) = tokio::time::timeout(timeout, notifier.acquire()).await {
+                    permit.unwrap().forget();
+                }
+            }
+            let resp = s.get_response(&dest, stream_id, 0).await;
+            s.close_stream(&dest, stream_id).await;
+            let resp = match resp {
+                Some(resp) => resp,
+                None => return Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::TimedOut,
+                    format!("{:?} did not answer the delegation of cap {:?}", dest, self.cap_id),
+                )),
+            };
+            if let Ok(full) = CapTableFullHeader::try_from(&resp.data[..]) {
+                s.drop_rejected_delegation(self, &delegatee).await;
+                return Err(tokio::io::Error::other(
+                    format!("cap table full, {:?} holds its maximum of {:?} received caps", dest, { full.capacity }),
+                ));
+            }
+            match RequestResponseHeader::try_from(&resp.data[..]) {
+                Ok(resp) if resp.response_code == RESPONSE_OK => Ok(()),
+                _ => {
+                    s.drop_rejected_delegation(self, &delegatee).await;
+                    Err(tokio::io::Error::new(
+                        tokio::io::ErrorKind::PermissionDenied,
+                        format!("{:?} refused the delegation of cap {:?}", dest, self.cap_id),
+                    ))
+                }
             }
-            
-            let dest: String = delegatee.into();
-            let _ = self.service.as_ref().unwrap().send(SendRequest::new(dest, packet), false).await;
-            
-            Ok(())
         }
 
         /**
-         * Revoke all delegations of the capability
+         * Revoke all delegations of the capability and wait until the delegatees and the control plane acknowledged it
          */
-        pub async fn revoke(&self, s: Service) -> tokio::io::Result<()> {
-            let address = s.config.address.clone();
-            let packet: Box<[u8; std::mem::size_of::<RevokeCapHeader>()]> =
-                RevokeCapHeader::construct(self, address.as_str().into()).into();
+        pub async fn revoke(&self, s: Service) -> tokio::io::Result<RevocationReport> {
+            if !self.rights.contains(Rights::REVOKE) {
+                return Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::PermissionDenied,
+                    format!("cap {:?} with rights {:?} cannot be revoked", self.cap_id, self.rights),
+                ));
+            }
+            self.revoke_delegations(s).await
+        }
 
-            debug!("packet to be send: {:?}", packet);
+        /// Revoke all delegations without checking the own rights, used when the owner revoked the capability or on shutdown
+        pub(crate) async fn revoke_delegations(&self, s: Service) -> tokio::io::Result<RevocationReport> {
+            let owner: IpAddress = s.config.advertised_address().into();
+            let mut revocations: Vec<(String, IpAddress)> = Vec::new();
 
             #[cfg(feature="directCPcommunication")]
-            {
-                let ctrl_plane = self.service.as_ref().unwrap().config.switch_addr.clone();
-                let _ = s
-                    .send(SendRequest::new(ctrl_plane, packet.clone()), false)
-                    .await;
-            }
+            revocations.push((s.config.switch_addr.clone(), owner));
 
-            for delegatee in self.delegatees.lock().await.clone() {
-                let _ = s
-                    .send(SendRequest::new(delegatee.into(), packet.clone()), false)
-                    .await;
+            // the owner revokes every copy in the derivation tree directly, other nodes only know their own delegatees
+            let mut targets = self.delegatees.lock().await.clone();
+            for derivation in self.derivations.lock().await.drain(..) {
+                if !targets.iter().any(|t| t.same_node(&derivation.delegatee)) {
+                    targets.push(derivation.delegatee);
+                }
             }
+            revocations.extend(targets.into_iter().map(|delegatee| (delegatee.into(), owner)));
+
             s.cap_table.remove(self.cap_id).await;
-            Ok(())
+            Ok(self.send_revocations(&s, revocations).await)
+        }
+
+        /// Revoke the delegation to `delegatee` and everything derived from it, used when its lease expired
+        pub(crate) async fn revoke_delegatee(&self, s: &Service, delegatee: IpAddress) -> RevocationReport {
+            let owner: IpAddress = s.config.advertised_address().into();
+            let subtree = self.subtree(delegatee).await;
+            self.delegatees.lock().await.retain(|d| !d.same_node(&delegatee));
+            self.derivations.lock().await.retain(|d| !subtree.iter().any(|n| n.same_node(&d.delegatee)));
+
+            let mut revocations: Vec<(String, IpAddress)> = Vec::new();
+            for node in subtree {
+                #[cfg(feature="directCPcommunication")]
+                revocations.push((s.config.switch_addr.clone(), node));
+                revocations.push((node.into(), owner));
+            }
+            self.send_revocations(s, revocations).await
+        }
+
+        /// Send a `CapRevoke` naming `node` to each destination in parallel and collect the acknowledgements
+        async fn send_revocations(&self, s: &Service, revocations: Vec<(String, IpAddress)>) -> RevocationReport {
+            let handles: Vec<_> = revocations
+                .into_iter()
+                .map(|(dest, node)| {
+                    let (cap, s) = (self.clone(), s.clone());
+                    tokio::spawn(async move {
+                        let confirmed = cap.send_revocation(&s, dest.clone(), node).await;
+                        (dest, confirmed)
+                    })
+                })
+                .collect();
+
+            let mut report = RevocationReport::default();
+            for handle in handles {
+                let (dest, confirmed) = handle.await.unwrap();
+                if dest != s.config.switch_addr {
+                    let outcome = if confirmed { AuditOutcome::Success } else { AuditOutcome::Failed };
+                    s.audit(AuditEvent::Revoke, self.cap_id, &dest, outcome);
+                }
+                match confirmed {
+                    true => report.confirmed.push(dest),
+                    false => report.timed_out.push(dest),
+                }
+            }
+            report
         }
 
-        pub async fn revoke_on_node(&self, s: Service, node: IpAddress) -> tokio::io::Result<()> {
+        /// Send a `CapRevoke` to `dest` until it is acknowledged or the retries are used up
+        async fn send_revocation(&self, s: &Service, dest: String, node: IpAddress) -> bool {
+            let stream_id = s.open_stream(&dest).await;
             let packet: Box<[u8; std::mem::size_of::<RevokeCapHeader>()]> =
-                RevokeCapHeader::construct(self, node).into();
+                RevokeCapHeader::construct(self, node, stream_id).into();
+            let timeout = Duration::from_millis(s.config.revoke_ack_timeout_ms);
 
-            debug!("packet to be send: {:?}", packet);
+            let mut confirmed = false;
+            for attempt in 0..=s.config.revoke_retries {
+                debug!("sending revocation of cap {:?} to {:?}, attempt {:?}", self.cap_id, dest, attempt);
+                let notifier = s
+                    .send(SendRequest::new(dest.clone(), packet.clone()), true)
+                    .await
+                    .unwrap();
+                match tokio::time::timeout(timeout, notifier.acquire()).await {
+                    Ok(permit) => permit.unwrap().forget(),
+                    Err(_) => continue,
+                };
+                let resp = s.get_response(&dest, stream_id, 0).await;
+                if let Some(Ok(_)) = resp.map(|resp| RevokeAckHeader::try_from(&resp.data[..])) {
+                    confirmed = true;
+                    break;
+                }
+            }
+            s.close_stream(&dest, stream_id).await;
+            if !confirmed {
+                warn!("revocation of cap {:?} not acknowledged by {:?}", self.cap_id, dest);
+            }
+            confirmed
+        }
 
-            #[cfg(feature="directCPcommunication")]
-            {
-                let ctrl_plane = self.service.as_ref().unwrap().config.switch_addr.clone();
-                let _ = s
-                    .send(SendRequest::new(ctrl_plane, packet.clone()), false)
-                    .await;
+        /**
+         * Extend the lease of a leased capability to `duration` from now.
+         * The owner never extends a lease further than the duration it was granted for
+         */
+        pub async fn renew_lease(&mut self, duration: Duration) -> Result<(), ()> {
+            if self.lease_expiry_ms == 0 {
+                error!("cap {:?} is not leased", self.cap_id);
+                return Err(());
+            }
+            let service = self.service.as_ref().unwrap().clone();
+            let owner: String = self.owner_address.into();
+            let stream_id = service.open_stream(&owner).await;
+            let packet: Box<[u8; std::mem::size_of::<LeaseRenewHeader>()]> =
+                LeaseRenewHeader::construct(self.cap_id, stream_id, u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)).into();
+
+            if let Some(notifier) = service.send(SendRequest::new(owner.clone(), packet), true).await {
+                let timeout = Duration::from_millis(service.config.response_timeout_ms);
+                if let Ok(permit) = tokio::time::timeout(timeout, notifier.acquire()).await {
+                    permit.unwrap().forget();
+                }
+            }
+            let resp = service.get_response(&owner, stream_id, 0).await;
+            service.close_stream(&owner, stream_id).await;
+            // expired leases are answered with CapInvalid
+            match resp.map(|resp| LeaseRenewResponseHeader::try_from(&resp.data[..])) {
+                Some(Ok(resp)) => {
+                    self.lease_expiry_ms = resp.lease_expiry_ms;
+                    self.refresh_table_entry();
+                    Ok(())
+                }
+                Some(Err(e)) => {
+                    debug!("lease renewal of cap {:?} not answered with a LeaseRenewResponse: {:?}", self.cap_id, e);
+                    Err(())
+                }
+                None => {
+                    warn!("owner {:?} did not answer the lease renewal of cap {:?}", owner, self.cap_id);
+                    Err(())
+                }
             }
-            Ok(())
         }
 
-        pub async fn request_invoke(&self) -> Result<(), ()> {
+        pub async fn revoke_on_node(&self, s: Service, node: IpAddress) -> tokio::io::Result<RevocationReport> {
+            #[allow(unused_mut)]
+            let mut revocations: Vec<(String, IpAddress)> = Vec::new();
+
+            #[cfg(feature="directCPcommunication")]
+            revocations.push((s.config.switch_addr.clone(), node));
+
+            Ok(self.send_revocations(&s, revocations).await)
+        }
+
+        pub async fn request_invoke(&self) -> Result<(), InvokeError> {
             self.request_invoke_with_continuation(vec!()).await
         }
 
-        pub async fn request_invoke_no_wait(&self) -> Result<(), ()> {
+        pub async fn request_invoke_no_wait(&self) -> Result<(), InvokeError> {
             self.request_invoke_with_continuation_no_wait(vec!()).await
         }
 
-        pub async fn request_invoke_with_continuation(&self, continuations: Vec<CapID>) -> Result<(), ()> {
+        pub async fn request_invoke_with_continuation(&self, continuations: Vec<CapID>) -> Result<(), InvokeError> {
             self.request_invoke_with_continuation_wait_param(continuations, true).await
         }
 
-        pub async fn request_invoke_with_continuation_no_wait(&self, continuations: Vec<CapID>) -> Result<(), ()> {
+        pub async fn request_invoke_with_continuation_no_wait(&self, continuations: Vec<CapID>) -> Result<(), InvokeError> {
             self.request_invoke_with_continuation_wait_param(continuations, false).await
         }
 
-        async fn request_invoke_with_continuation_wait_param(&self, continuations: Vec<CapID>, wait: bool) -> Result<(), ()> {
+        async fn request_invoke_with_continuation_wait_param(&self, continuations: Vec<CapID>, wait: bool) -> Result<(), InvokeError> {
             debug!("in request invocation with cont handler");
-
-            let mut cont_ids: [CapID; 4] = [0;4];
-            for i in 0..4.min(continuations.len()) {
-                cont_ids[i] = continuations[i];
+            if !self.rights.contains(Rights::INVOKE) {
+                error!("cap {:?} with rights {:?} cannot be invoked", self.cap_id, self.rights);
+                return Err(InvokeError::PermissionDenied);
             }
-            debug!("capids for continuations are: {:?}", cont_ids.clone());
+
+            debug!("capids for continuations are: {:?}", continuations);
 
             let mut flags = Flags::empty();
             flags.set(Flags::REQUIRE_RESPONSE, wait);
 
-            let (stream_id, p) = RequestInvokeHeader::construct(self.clone(), continuations.len() as u8, cont_ids, flags);
+            let service = self.service.as_ref().unwrap();
+            let owner: String = self.owner_address.into();
+            let sequence = service.next_invoke_sequence(&owner).await;
+            // responses are only routed to open streams, invocations without response do not need one
+            let stream_id = match wait {
+                true => service.open_stream(&owner).await,
+                false => rand::thread_rng().gen::<u32>(),
+            };
+            let p = RequestInvokeHeader::construct(self, &continuations, flags, sequence, stream_id);
             let packet: Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]> = p.into();
             
 
-            let notifier = self.service.as_ref().unwrap()
-                .send(SendRequest::new(self.owner_address.into(), packet), wait)
+            let notifier = service
+                .send(SendRequest::new(owner.clone(), packet), wait)
                 .await;
             if wait {
                 debug!("Waiting for Response");
-                let _ = notifier.unwrap().acquire().await.unwrap();
+                let timeout = Duration::from_millis(service.config.response_timeout_ms);
+                match tokio::time::timeout(timeout, notifier.unwrap().acquire()).await {
+                    Ok(permit) => permit.unwrap().forget(),
+                    Err(_) => {
+                        warn!("invocation of cap {:?} not answered by {:?}", self.cap_id, owner);
+                        service.close_stream(&owner, stream_id).await;
+                        return Err(InvokeError::Timeout);
+                    }
+                };
                 debug!("Notified of response");
-                let resp = self.service.as_ref().unwrap().get_response(stream_id).await;
-                debug!("Packet type is {:?}", CmdType::from(* bytemuck::from_bytes::<u32>(&resp.as_ref().unwrap().data[12..16])));
-                if CmdType::from(* bytemuck::from_bytes::<u32>(&resp.as_ref().unwrap().data[12..16])) != CmdType::RequestResponse {
-                    return Err(());
-                }
-
-                let resp = RequestResponseHeader::from(resp.unwrap().data);
-                if resp.response_code != 0 {
-                    return Err(());
+                let resp = service.get_response(&owner, stream_id, 0).await;
+                service.close_stream(&owner, stream_id).await;
+                let resp = match resp {
+                    Some(resp) => resp,
+                    None => return Err(InvokeError::Invalid),
+                };
+                if let Ok(stale) = StaleEpochHeader::try_from(&resp.data[..]) {
+                    warn!("cap {:?} of epoch {:?} is stale, owner is in epoch {:?}", self.cap_id, self.epoch, { stale.epoch });
+                    return Err(InvokeError::StaleEpoch);
                 }
+                // anything else but a RequestResponse, e.g. CapInvalid, fails the invocation
+                let resp = match RequestResponseHeader::try_from(&resp.data[..]) {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        debug!("invocation of cap {:?} not answered with a RequestResponse: {:?}", self.cap_id, e);
+                        return Err(InvokeError::Invalid);
+                    }
+                };
+                return match resp.response_code {
+                    RESPONSE_OK => Ok(()),
+                    RESPONSE_FAILED => Err(InvokeError::Failed),
+                    _ => Err(InvokeError::Invalid),
+                };
             }
             Ok(())
         }
@@ -278,69 +897,134 @@ pub mod tcap {
             }
         }
 
-        pub async fn get_buffer(&mut self) -> Arc<Mutex<MemoryObject>> {
+        /// Memory object of a memory capability held locally, without copying it from the owner
+        pub(crate) async fn local_buffer(&self) -> Option<Arc<Mutex<MemoryObject>>> {
+            match self.memory_object.as_ref() {
+                Some(object) if object.lock().await.is_local().await => Some(object.clone()),
+                _ => None,
+            }
+        }
+
+        /// Memory object of a memory capability, copied from the owner on first access.
+        /// Only complete copies are kept, a failed or incomplete transfer is retried on the next call
+        pub async fn get_buffer(&mut self) -> Result<Arc<Mutex<MemoryObject>>, InvokeError> {
             if self.cap_type != CapType::Memory {
-                panic!("get_buffer() can only be called on memory capabilities");
+                error!("get_buffer() called on cap {:?} of type {:?}", self.cap_id, self.cap_type);
+                return Err(InvokeError::Invalid);
             }
 
-            let local: bool = self.memory_object.is_some() && self.memory_object.as_ref().unwrap().lock().await.is_local().await;
+            if let Some(object) = self.local_buffer().await {
+                return Ok(object);
+            }
 
-            match local {
-                true => {
-                    self.memory_object.as_ref().unwrap().clone()
+            if !self.rights.contains(Rights::READ) {
+                return Err(InvokeError::PermissionDenied);
+            }
+            let service = self.service.as_ref().unwrap().clone();
+            let owner: String = self.owner_address.into();
+            let chunk_size = service.config.chunk_size_for(&owner);
+            let stream_id = service.open_stream(&owner).await;
+            let data = MemoryCopyRequestHeader::construct(self.cap_id, stream_id, chunk_size as u32, self.rights, self.granted_rights, self.epoch, self.mac());
+            let data: Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]> = data.into();
+
+            let req = SendRequest::new(owner.clone(), data);
+
+            let notifier = match service.send(req, true).await {
+                Some(notifier) => notifier,
+                None => {
+                    service.close_stream(&owner, stream_id).await;
+                    return Err(InvokeError::Failed);
                 }
-                false => {
-                    let (stream_id, data) = MemoryCopyRequestHeader::construct(self.cap_id);
-                    let data: Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]> = data.into();
-
-                    let req = SendRequest::new(self.owner_address.into(), data);
-
-                    match self.service.as_ref().unwrap().send(req, true).await {
-                        None => {
-                            panic!("Response to MemoryCopy Request should not be None");
-                        }
-                        Some(notifier) => {
-                            let _ = notifier.acquire().await.unwrap();
-
-                            // first packet has sequence ID one
-                            let mut sequence = 1;
-                            debug!("get stream_id resp {:?}, currently avalable: {:?}", sequence+stream_id, self.service.as_ref().unwrap().responses.lock().await.keys());
-                            while ! self.service.as_ref().unwrap().responses.lock().await.contains_key(&(stream_id + 1)) {
-                               tokio::time::sleep(std::time::Duration::from_nanos(10)).await; 
-                            }
-                            let resp = self.service.as_ref().unwrap().get_response_no_delete(stream_id + sequence).await.unwrap();
-                            let resp = MemoryCopyResponseHeader::from(resp.data);
-                            self.memory_object = Some(Arc::new(Mutex::new(MemoryObject::from(resp))));
-                            
-                            // wait for all packets to be in response buffers
-                            let num_packets = resp.buf_size.div_ceil(resp.size);
-                            //first packet already arrived
-                            if num_packets > 1 {
-                                let _  = notifier.acquire_many((num_packets-1) as u32).await.unwrap();
-                            }
+            };
 
-                            let stream_id = stream_id - resp.sequence;
-                            debug!("all notifiers triggered");
-                            let buf_size =  resp.buf_size;
-                            debug!("get stream_id resp {:?}, currently avalable: {:?}, buf_size {:?}", sequence+stream_id, self.service.as_ref().unwrap().responses.lock().await.keys(), buf_size);
-                            //extract all packets from response buffers
-
-                            while self.memory_object.as_ref().unwrap().lock().await.size < resp.buf_size {
-                                sequence += 1;
-                                if let Some(resp) = self.service.as_ref().unwrap().get_response(stream_id + sequence).await {
-                                    let resp = MemoryCopyResponseHeader::from(resp.data);
-                                    let seq =  resp.sequence;
-                                    self.memory_object.as_ref().unwrap().lock().await.append(resp);
-                                } else {
-                                    debug!("packet missing in memcpy buffer constructor. Trying to access {:?}", stream_id + sequence)
-                                }
-                            }
+            let mut object: Option<MemoryObject> = None;
+            let mut buf_size = 0;
+            // first packet has sequence ID one
+            let mut sequence: u32 = 1;
 
-                            self.memory_object.as_ref().unwrap().clone()
-                        }
+            // chunks may arrive out of order or repeatedly, as the sender retransmits unacknowledged chunks
+            let result = loop {
+                if object.as_ref().is_some_and(|object| object.size >= buf_size) {
+                    break Ok(object.take().unwrap());
+                }
+                // refusals of the owner are not chunks and carry no sequence number
+                if let Some(resp) = service.get_response(&owner, stream_id, 0).await {
+                    if let Ok(stale) = StaleEpochHeader::try_from(&resp.data[..]) {
+                        warn!("memory cap {:?} of epoch {:?} is stale, owner is in epoch {:?}", self.cap_id, self.epoch, { stale.epoch });
+                        break Err(InvokeError::StaleEpoch);
                     }
+                    warn!("owner refused the memory copy of cap {:?}", self.cap_id);
+                    break Err(InvokeError::Invalid);
                 }
+                match service.get_response(&owner, stream_id, sequence).await {
+                    Some(resp) => {
+                        let resp = MemoryCopyResponse::try_from(&resp.data[..])
+                            .expect("memory copy chunks are validated by the receive loop");
+                        match object.as_mut() {
+                            None => {
+                                buf_size = resp.header.buf_size;
+                                object = Some(MemoryObject::from(resp));
+                            }
+                            Some(object) => object.append(resp),
+                        };
+                        sequence += 1;
+                    }
+                    None => {
+                        let timeout = std::time::Duration::from_millis(MEMCOPY_ACK_TIMEOUT_MS * (MEMCOPY_MAX_RETRANSMITS as u64 + 1));
+                        match tokio::time::timeout(timeout, notifier.acquire()).await {
+                            Ok(permit) => permit.unwrap().forget(),
+                            Err(_) => {
+                                error!("memory copy of cap {:?} timed out waiting for chunk {:?}", self.cap_id, sequence);
+                                break Err(InvokeError::Timeout);
+                            }
+                        };
+                    }
+                }
+            };
+            service.close_stream(&owner, stream_id).await;
+
+            let object = Arc::new(Mutex::new(result?));
+            debug!("all chunks of stream {:?} received", stream_id);
+            self.memory_object = Some(object.clone());
+            self.refresh_table_entry();
+            Ok(object)
+        }
+    }
+
+    mod tests {
+        #![allow(unused_imports)]
+        use super::{is_valid_delegation_depth, subtree, Derivation, UNLIMITED_DELEGATION_DEPTH};
+        use crate::packet_types::tcap::IpAddress;
+
+        #[test]
+        fn test_delegation_depth() {
+            assert!(!is_valid_delegation_depth(0, 0), "depth zero caps are not transferable");
+            assert!(is_valid_delegation_depth(1, 0));
+            assert!(!is_valid_delegation_depth(1, 1), "delegations must decrease the depth");
+            assert!(!is_valid_delegation_depth(2, UNLIMITED_DELEGATION_DEPTH));
+            assert!(is_valid_delegation_depth(UNLIMITED_DELEGATION_DEPTH, UNLIMITED_DELEGATION_DEPTH));
+            assert!(is_valid_delegation_depth(UNLIMITED_DELEGATION_DEPTH, 0));
+        }
+
+        #[test]
+        fn test_subtree() {
+            let node = |i: u8| IpAddress::from(format!("10.0.0.{}:1234", i).as_str());
+            let edge = |delegator: u8, delegatee: u8| Derivation { delegator: node(delegator), delegatee: node(delegatee), depth: 0 };
+            // 1 -> 2 -> 3 -> 4, 2 -> 5, 1 -> 6
+            let derivations = vec![edge(1, 2), edge(2, 3), edge(3, 4), edge(2, 5), edge(1, 6)];
+
+            let nodes = subtree(&derivations, node(2));
+            assert_eq!(nodes.len(), 4);
+            for i in [2, 3, 4, 5] {
+                assert!(nodes.iter().any(|n| n.same_node(&node(i))), "node {} is derived from 2", i);
             }
+            assert!(subtree(&derivations, node(1)).len() == 6);
+            assert!(subtree(&derivations, node(4)).len() == 1, "leaves have no subtree");
+
+            // 3 delegates back to 2 and 4 to 1, every node is visited once
+            let cyclic = [derivations.clone(), vec![edge(3, 2), edge(4, 1)]].concat();
+            assert_eq!(subtree(&cyclic, node(2)).len(), 6);
+            assert_eq!(subtree(&cyclic, node(1)).len(), 6);
         }
     }
 }
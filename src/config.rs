@@ -1,5 +1,10 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
 use clap::Parser;
 
+use crate::{MEMCOPY_BUFFER_SIZE, MEMCOPY_MAX_CHUNK_SIZE};
+
 #[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
@@ -7,11 +12,195 @@ pub struct Config {
     #[arg(short, long)]
     pub interface: String,
 
-    /// Address to bind to (including port number)
+    /// Address to bind to (including port number), port 0 binds to a free port
     #[arg(short, long)]
     pub address: String,
 
+    /// Address announced to peers as owner of local capabilities (including port number).
+    /// Defaults to the bind address, set it when binding to 0.0.0.0 or behind address translation
+    #[arg(long)]
+    pub advertise_address: Option<String>,
+
     /// Address of the switch control plane (including port number)
     #[arg(short, long)]
     pub switch_addr: String,
+
+    /// Maximum number of memory copy chunks sent before waiting for acknowledgements
+    #[arg(long, default_value_t = 32)]
+    pub memcopy_window: usize,
+
+    /// Memory copy chunk size in bytes, used for peers without a peer specific chunk size
+    #[arg(long, default_value_t = MEMCOPY_BUFFER_SIZE)]
+    pub memcopy_chunk_size: usize,
+
+    /// Peer specific memory copy chunk size as <address:port>=<bytes>, e.g. for jumbo frames
+    #[arg(long = "peer-chunk-size", value_parser = parse_peer_option::<usize>)]
+    pub peer_chunk_sizes: Vec<(String, usize)>,
+
+    /// Number of events buffered for each subscriber, slow subscribers miss the oldest events
+    #[arg(long, default_value_t = 256)]
+    pub event_queue_size: usize,
+
+    /// Number of shards of the capability table, lookups of caps in different shards do not contend
+    #[arg(long, default_value_t = 16)]
+    pub cap_table_shards: usize,
+
+    /// Maximum number of capabilities owned by this service, creating more fails
+    #[arg(long)]
+    pub max_owned_caps: Option<usize>,
+
+    /// Maximum number of capabilities received from other services.
+    /// When full, only received caps whose lease expired are evicted to make room, caps delegated without a lease
+    /// are never evicted. Delegations are rejected while no cap can be evicted
+    #[arg(long)]
+    pub max_received_caps: Option<usize>,
+
+    /// Time in milliseconds to wait for queued packets and running handlers when shutting down
+    #[arg(long, default_value_t = 5000)]
+    pub shutdown_timeout_ms: u64,
+
+    /// Time in milliseconds to wait for the acknowledgement of a revocation before it is resent
+    #[arg(long, default_value_t = 100)]
+    pub revoke_ack_timeout_ms: u64,
+
+    /// Number of times an unacknowledged revocation is resent before the node is reported as timed out
+    #[arg(long, default_value_t = 3)]
+    pub revoke_retries: u32,
+
+    /// Time in milliseconds to wait for the owner to answer the invocation, delegation or lease renewal of a capability held by this service
+    #[arg(long, default_value_t = 1000)]
+    pub response_timeout_ms: u64,
+
+    /// Path of a unix datagram socket to bind for traffic with co-located peers
+    #[arg(long)]
+    pub unix_socket: Option<String>,
+
+    /// Co-located peer reached via its unix datagram socket as <address:port>=<path>
+    #[arg(long = "local-peer", value_parser = parse_peer_option::<String>)]
+    pub local_peers: Vec<(String, String)>,
+
+    /// Hex encoded secret key authenticating the capabilities delegated by this service.
+    /// A random key is generated if unset
+    #[arg(long)]
+    pub mac_key: Option<String>,
+
+    /// Pre-shared key encrypting all packets exchanged with a peer as <address:port>=<64 hex digits>.
+    /// The common header stays readable for the switch, everything after it is encrypted and authenticated
+    #[arg(long = "peer-key", value_parser = parse_peer_option::<String>)]
+    pub peer_keys: Vec<(String, String)>,
+
+    /// Only serve invocations and memory copies presenting a valid capability MAC.
+    /// Invocations carry a MAC keyed with the capability MAC over the whole header, which also authenticates
+    /// the sequence number and timestamp checked by `replay_protection` and `max_invoke_skew_ms`.
+    /// The capability MAC itself is sent with delegations and memory copies, set a `peer_key` to hide it from eavesdroppers.
+    /// Set to false to serve caps created without the owner's MAC, see `Service::create_remote_capability_with_id`
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+    pub require_mac: bool,
+
+    /// Only serve invocations and memory copies from the owner or a delegatee of the capability
+    #[arg(long, default_value_t = false)]
+    pub enforce_delegatees: bool,
+
+    /// Reject replayed RequestInvoke packets using a per-peer sequence number window.
+    /// The sequence number is only authenticated if `require_mac` is set
+    #[arg(long, default_value_t = false)]
+    pub replay_protection: bool,
+
+    /// Reject RequestInvoke packets with a timestamp more than this many milliseconds off the local clock.
+    /// The timestamp is only authenticated if `require_mac` is set
+    #[arg(long)]
+    pub max_invoke_skew_ms: Option<u64>,
+
+    /// Unsolicited packets accepted per second from one source, further packets are dropped
+    #[arg(long)]
+    pub source_rate_limit: Option<f64>,
+
+    /// Unsolicited packets of one opcode accepted per second from one source as <opcode>=<packets per second>
+    #[arg(long = "opcode-rate-limit", value_parser = parse_opcode_rate)]
+    pub opcode_rate_limits: Vec<(u32, f64)>,
+
+    /// Number of packets a source may send at once before the rate limits apply
+    #[arg(long, default_value_t = 16.0)]
+    pub rate_limit_burst: f64,
+
+    /// File to append an audit record of every capability operation to
+    #[arg(long)]
+    pub audit_log: Option<String>,
+
+    /// Size in bytes after which the audit log is rotated
+    #[arg(long, default_value_t = 16 * 1024 * 1024)]
+    pub audit_log_max_bytes: u64,
+
+    /// Number of rotated audit log files to keep
+    #[arg(long, default_value_t = 4)]
+    pub audit_log_files: usize,
+
+    /// Number of audit records queued for writing, further records are dropped and counted while the queue is full
+    #[arg(long, default_value_t = 1024)]
+    pub audit_log_queue_size: usize,
+
+    /// Remove owned capabilities and their objects once all holders closed them or their leases expired
+    #[arg(long, default_value_t = false)]
+    pub reclaim_unheld_objects: bool,
+
+    /// File persisting the owned capabilities and the MAC key, written by `Service::snapshot` and on shutdown.
+    /// Owned capabilities are not revoked on shutdown when set, `Service::restore` reads them back
+    #[arg(long)]
+    pub cap_snapshot: Option<String>,
+
+    /// Interval in milliseconds in which the capability snapshot is written while the service runs
+    #[arg(long)]
+    pub cap_snapshot_interval_ms: Option<u64>,
+}
+
+impl Config {
+    /// Address used as owner address in all outgoing headers
+    pub fn advertised_address(&self) -> &str {
+        self.advertise_address.as_deref().unwrap_or(self.address.as_str())
+    }
+
+    /// Replace port 0 in the bind and the advertised address with `port`, the port the socket was bound to
+    pub(crate) fn set_bound_port(&mut self, port: u16) {
+        for address in std::iter::once(&mut self.address).chain(self.advertise_address.as_mut()) {
+            if let Ok(mut bound) = address.parse::<SocketAddr>() {
+                if bound.port() == 0 {
+                    bound.set_port(port);
+                    *address = bound.to_string();
+                }
+            }
+        }
+    }
+
+    /// Memory copy chunk size to use for transfers with `peer`
+    pub fn chunk_size_for(&self, peer: &str) -> usize {
+        let chunk_size = self.peer_chunk_sizes
+            .iter()
+            .find(|(address, _)| address == peer)
+            .map(|(_, chunk_size)| *chunk_size)
+            .unwrap_or(self.memcopy_chunk_size);
+
+        chunk_size.clamp(1, MEMCOPY_MAX_CHUNK_SIZE)
+    }
+}
+
+/// Parse an opcode rate limit given as <opcode>=<packets per second>
+fn parse_opcode_rate(val: &str) -> Result<(u32, f64), String> {
+    let (opcode, rate) = val
+        .split_once('=')
+        .ok_or(format!("expected <opcode>=<packets per second>, got {:?}", val))?;
+    let opcode = opcode.parse::<u32>().map_err(|e| e.to_string())?;
+    let rate = rate.parse::<f64>().map_err(|e| e.to_string())?;
+    Ok((opcode, rate))
+}
+
+/// Parse a peer specific option given as <address:port>=<value>
+fn parse_peer_option<T: FromStr>(val: &str) -> Result<(String, T), String>
+where
+    T::Err: std::fmt::Display,
+{
+    let (address, value) = val
+        .split_once('=')
+        .ok_or(format!("expected <address:port>=<value>, got {:?}", val))?;
+    let value = value.parse::<T>().map_err(|e| e.to_string())?;
+    Ok((address.to_string(), value))
 }
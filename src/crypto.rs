@@ -0,0 +1,135 @@
+pub mod tcap {
+    pub(crate) mod crypto {
+        use core::fmt;
+        use std::{collections::HashMap, io, net::SocketAddr};
+
+        use chacha20poly1305::{
+            aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+            XChaCha20Poly1305, XNonce,
+        };
+
+        use crate::{auth::tcap::auth::decode_hex, config::Config, packet_types::tcap::CommonHeader};
+
+        /// Size of the random nonce sent in front of the ciphertext
+        pub(crate) const NONCE_SIZE: usize = 24;
+        /// Size of the authentication tag appended to the ciphertext
+        pub(crate) const TAG_SIZE: usize = 16;
+
+        /// Ciphers of all peers configured with a pre-shared key.
+        /// Encrypted packets keep the `CommonHeader` in clear text, it is authenticated as associated data.
+        /// Layout: CommonHeader | nonce | ciphertext | tag
+        pub(crate) struct PeerCiphers {
+            ciphers: HashMap<SocketAddr, XChaCha20Poly1305>,
+        }
+
+        impl fmt::Debug for PeerCiphers {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct("PeerCiphers")
+                    .field("peers", &self.ciphers.keys().collect::<Vec<_>>())
+                    .finish()
+            }
+        }
+
+        impl PeerCiphers {
+            pub(crate) fn from_config(config: &Config) -> io::Result<PeerCiphers> {
+                let mut ciphers = HashMap::new();
+                for (address, key) in config.peer_keys.iter() {
+                    let peer = address.parse::<SocketAddr>()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid peer key address {:?}: {}", address, e)))?;
+                    let key = decode_hex(key)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key for peer {:?}: {}", address, e)))?;
+                    let cipher = XChaCha20Poly1305::new_from_slice(&key)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("key for peer {:?} must be 32 bytes", address)))?;
+                    ciphers.insert(peer, cipher);
+                }
+                Ok(PeerCiphers { ciphers })
+            }
+
+            fn cipher(&self, peer: &SocketAddr) -> Option<&XChaCha20Poly1305> {
+                self.ciphers.get(peer)
+            }
+
+            /// Encrypt `packet` for `dest`, or `None` if no key is configured for it
+            pub(crate) fn seal(&self, dest: &str, packet: &[u8]) -> Option<Vec<u8>> {
+                let cipher = self.cipher(&dest.parse::<SocketAddr>().ok()?)?;
+                Some(seal(cipher, packet))
+            }
+
+            /// Decrypt `packet` received from `sender`.
+            /// Packets of peers without a key are returned unchanged, `Err` means the packet was not authentic.
+            pub(crate) fn open(&self, sender: &SocketAddr, packet: Vec<u8>) -> Result<Vec<u8>, ()> {
+                match self.cipher(sender) {
+                    Some(cipher) => open(cipher, &packet),
+                    None => Ok(packet),
+                }
+            }
+        }
+
+        fn seal(cipher: &XChaCha20Poly1305, packet: &[u8]) -> Vec<u8> {
+            let header_size = std::mem::size_of::<CommonHeader>().min(packet.len());
+            let (header, payload) = packet.split_at(header_size);
+            let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .encrypt(&nonce, Payload { msg: payload, aad: header })
+                .expect("encryption of a datagram sized payload cannot fail");
+
+            let mut sealed = Vec::with_capacity(header.len() + NONCE_SIZE + ciphertext.len());
+            sealed.extend_from_slice(header);
+            sealed.extend_from_slice(&nonce);
+            sealed.extend_from_slice(&ciphertext);
+            sealed
+        }
+
+        fn open(cipher: &XChaCha20Poly1305, packet: &[u8]) -> Result<Vec<u8>, ()> {
+            let header_size = std::mem::size_of::<CommonHeader>();
+            if packet.len() < header_size + NONCE_SIZE + TAG_SIZE {
+                return Err(());
+            }
+            let (header, rest) = packet.split_at(header_size);
+            let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+            let payload = cipher
+                .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+                .map_err(|_| ())?;
+
+            let mut opened = Vec::with_capacity(header.len() + payload.len());
+            opened.extend_from_slice(header);
+            opened.extend_from_slice(&payload);
+            Ok(opened)
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use chacha20poly1305::{aead::KeyInit, XChaCha20Poly1305};
+
+            use super::{open, seal, NONCE_SIZE, TAG_SIZE};
+            use crate::packet_types::tcap::{CmdType, CommonHeader};
+
+            #[test]
+            fn test_seal_open() {
+                let cipher = XChaCha20Poly1305::new_from_slice(&[7u8; 32]).unwrap();
+                let header = CommonHeader {
+                    size: 40,
+                    stream_id: 3,
+                    cmd: CmdType::RequestInvoke as u32,
+                    cap_id: 42,
+                };
+                let header = bytemuck::bytes_of(&header);
+                let mut packet = header.to_vec();
+                packet.extend_from_slice(&[1u8; 16]);
+
+                let sealed = seal(&cipher, &packet);
+                assert!(sealed.len() == packet.len() + NONCE_SIZE + TAG_SIZE);
+                assert!(sealed[..header.len()] == header[..], "common header must stay readable");
+                assert!(sealed[header.len() + NONCE_SIZE..header.len() + NONCE_SIZE + 16] != [1u8; 16], "payload must be encrypted");
+                assert!(open(&cipher, &sealed) == Ok(packet));
+
+                let mut tampered = sealed.clone();
+                tampered[12] ^= 1;
+                assert!(open(&cipher, &tampered).is_err(), "modified header must be rejected");
+
+                let other = XChaCha20Poly1305::new_from_slice(&[8u8; 32]).unwrap();
+                assert!(open(&other, &sealed).is_err(), "packet must not open with a different key");
+            }
+        }
+    }
+}
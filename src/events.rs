@@ -0,0 +1,24 @@
+pub mod tcap {
+    pub mod events {
+        use crate::capabilities::tcap::CapID;
+
+        /// Events published to the subscribers of a service, see `Service::subscribe`
+        #[derive(Clone, Debug, PartialEq)]
+        pub enum ServiceEvent {
+            /// `delegator` delegated the capability to this service, it is in the cap table and ready to use
+            CapInserted { cap_id: CapID, delegator: String },
+            /// the copy of the capability held by this service was revoked by `revoker`
+            CapRevoked { cap_id: CapID, revoker: String },
+            /// `caller` invoked a capability owned by this service, `success` is false if the handler failed
+            Invoked { cap_id: CapID, caller: String, success: bool },
+            /// a request of `peer` was rejected with a `CapInvalid`, e.g. because the capability is unknown
+            CapInvalid { cap_id: CapID, peer: String },
+            /// the lease of the received capability expired and it was dropped to make room for another one, see `Config::max_received_caps`
+            CapEvicted { cap_id: CapID },
+            /// `holder` closed its copy of a capability owned by this service, published after reclaiming the cap if it was the last holder
+            CapClosed { cap_id: CapID, holder: String },
+            /// `delegatee` rejected the delegation of the capability, e.g. as it holds the maximum number of received caps
+            DelegationRejected { cap_id: CapID, delegatee: String },
+        }
+    }
+}
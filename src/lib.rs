@@ -1,13 +1,29 @@
-#![feature(slice_first_last_chunk)]
 pub(crate) mod cap_table;
+pub(crate) mod crypto;
 pub(crate) mod packet_types;
+pub(crate) mod rate_limit;
+pub(crate) mod replay;
+pub(crate) mod snapshot;
+pub(crate) mod transport;
 
+pub mod audit;
+pub mod auth;
 pub mod capabilities;
+pub mod events;
 pub mod object;
 pub mod service;
 pub mod config;
 
+/// Default memory copy chunk size, used for peers without a configured chunk size
 pub const MEMCOPY_BUFFER_SIZE: usize = 4096;
+/// Largest chunk size that still fits into a single UDP datagram together with the response header
+pub const MEMCOPY_MAX_CHUNK_SIZE: usize = 65000;
+/// Size of the receive buffer, large enough for any datagram
+pub const RECV_BUFFER_SIZE: usize = 65536;
+/// Time to wait for a memory copy acknowledgement before the unacknowledged chunks are resent
+pub const MEMCOPY_ACK_TIMEOUT_MS: u64 = 100;
+/// Number of retransmissions without progress after which a memory copy is given up
+pub const MEMCOPY_MAX_RETRANSMITS: u32 = 10;
 
 // export objects in crate base mod
 #[allow(unused_imports)]
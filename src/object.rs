@@ -6,11 +6,16 @@ pub mod tcap {
         use std::sync::Arc;
 
         //TODO (@jkrbs): Refactor into Object Trait and multiple object types for Memory and Requests at least
-        use crate::{capabilities::tcap::Capability, packet_types::tcap::MemoryCopyResponseHeader};
+        use crate::{capabilities::tcap::Capability, packet_types::tcap::MemoryCopyResponse};
+
+        /// Handler registered with a service under a name, see `Service::register_handler`
+        pub type RequestHandler = Arc<dyn Fn(Vec<Option<Arc<Mutex<Capability>>>>) -> Result<(), ()> + Send + Sync>;
 
         pub struct RequestObject {
             is_local: bool,
             pub(crate) cap: Option<Capability>,
+            /// name of the registered handler, objects with a name are persisted in cap snapshots
+            name: Option<String>,
             function: Box<dyn Fn(Vec<Option<Arc<Mutex<Capability>>>>) -> Result<(), ()> + Send + Sync>,
         }
 
@@ -18,6 +23,7 @@ pub mod tcap {
             fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
                 f.debug_struct("RequestObject")
                     .field("is_local", &self.is_local)
+                    .field("name", &self.name)
                     .field("cap", &self.cap)
                     .finish()
             }
@@ -30,10 +36,25 @@ pub mod tcap {
                 RequestObject {
                     is_local: true,
                     cap: None,
+                    name: None,
                     function,
                 }
             }
 
+            /// Request object running the handler registered as `name`
+            pub(crate) async fn named(name: &str, handler: RequestHandler) -> RequestObject {
+                RequestObject {
+                    is_local: true,
+                    cap: None,
+                    name: Some(name.to_string()),
+                    function: Box::new(move |continuations| handler(continuations)),
+                }
+            }
+
+            pub fn name(&self) -> Option<&str> {
+                self.name.as_deref()
+            }
+
             pub async fn is_local(&self) -> bool {
                 self.is_local
             }
@@ -53,7 +74,7 @@ pub mod tcap {
                             Some(c) => c.blocking_lock().cap_id,
                             None => 0,
                         }
-                    }).collect()).await;
+                    }).collect()).await.map_err(|_| ());
                 }
             }
         }
@@ -65,12 +86,13 @@ pub mod tcap {
             pub(crate) data: Vec<u8>
         }
         
-        impl From<MemoryCopyResponseHeader> for MemoryObject {
-            fn from(value: MemoryCopyResponseHeader) -> Self {
+        impl From<MemoryCopyResponse> for MemoryObject {
+            fn from(mut value: MemoryCopyResponse) -> Self {
+                value.buffer.truncate(value.header.size as usize);
                 MemoryObject {
                     is_local: true,
-                    size: value.size,
-                    data: value.buffer.to_vec(),
+                    size: value.header.size,
+                    data: value.buffer,
                     cap: None
                 }
             }
@@ -111,11 +133,11 @@ pub mod tcap {
                 self.data.clone()
             }
 
-            pub(crate) fn append(&mut self, value: MemoryCopyResponseHeader) {
+            pub(crate) fn append(&mut self, value: MemoryCopyResponse) {
                 //TODO (@jkrbs): Check if cap is correct and all other field match
-                let extend = &value.buffer[..value.size as usize];
+                let extend = &value.buffer[..value.header.size as usize];
                 self.data.extend(extend);
-                self.size += value.size;
+                self.size += value.header.size;
             }
         }
     }
@@ -1,9 +1,10 @@
 pub mod tcap {
-    use crate::{MEMCOPY_BUFFER_SIZE, capabilities::tcap::{Capability, CapID}, object::tcap::object::MemoryObject};
+    use crate::{MEMCOPY_MAX_CHUNK_SIZE, auth::tcap::auth::{invocation_mac, CapMac}, capabilities::tcap::{Capability, CapID, CapType, Derivation, Rights}, object::tcap::object::MemoryObject};
     use bytemuck::*;
     use tokio::sync::Mutex;
     use std::{net::{Ipv4Addr, SocketAddrV4}, str::FromStr, sync::Arc};
     use bitflags::bitflags;
+    use crate::replay::tcap::replay::now_ms;
 
     #[repr(C)]
     #[derive(Clone, Copy, Pod, Zeroable, Debug)]
@@ -17,6 +18,11 @@ pub mod tcap {
         pub fn to_socket_addrs(&self) -> SocketAddrV4 {
             SocketAddrV4::new(self.address.into(), self.port)
         }
+
+        /// Whether both addresses refer to the same node, the netmask is ignored
+        pub fn same_node(&self, other: &IpAddress) -> bool {
+            self.address == other.address && self.port == other.port
+        }
     }
 
     impl From<&str> for IpAddress {
@@ -128,6 +134,13 @@ pub mod tcap {
 
         //nighP4 Implementation specific OP Codes
         InsertCap = 64,
+        MemoryCopyAck = 65,
+        LeaseRenew = 66,
+        LeaseRenewResponse = 67,
+        DelegationNotice = 68,
+        CapRevokeAck = 69,
+        CapStaleEpoch = 70,
+        CapTableFull = 71,
 
         ControllerResetSwitch = 128,
         ControllerStop = 129,
@@ -143,6 +156,22 @@ pub mod tcap {
         }
     }
 
+    impl CmdType {
+        /// Whether packets of this type are handled by this implementation
+        pub fn is_supported(&self) -> bool {
+            !matches!(
+                self,
+                CmdType::Nop
+                    | CmdType::CapGetInfo
+                    | CmdType::CapIsSame
+                    | CmdType::CapDiminish
+                    | CmdType::RequestCreate
+                    | CmdType::RequestReceive
+                    | CmdType::None
+            )
+        }
+    }
+
     impl From<u32> for CmdType {
         fn from(value: u32) -> Self {
             match value {
@@ -161,6 +190,13 @@ pub mod tcap {
                 17 => CmdType::RequestResponse,
                 32 => CmdType::None,
                 64 => CmdType::InsertCap,
+                65 => CmdType::MemoryCopyAck,
+                66 => CmdType::LeaseRenew,
+                67 => CmdType::LeaseRenewResponse,
+                68 => CmdType::DelegationNotice,
+                69 => CmdType::CapRevokeAck,
+                70 => CmdType::CapStaleEpoch,
+                71 => CmdType::CapTableFull,
 
                 128 => CmdType::ControllerResetSwitch,
                 129 => CmdType::ControllerStop,
@@ -180,10 +216,72 @@ pub mod tcap {
         pub(crate) cap_id: CapID,
     }
 
-    impl From<Vec<u8>> for CommonHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for CommonHeader {
+        type Error = DecodeError;
+
+        /// Decode the common header at the start of a datagram of any type
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let expected = std::mem::size_of::<CommonHeader>();
+            if value.len() < expected {
+                return Err(DecodeError::TooShort { expected, actual: value.len() });
+            }
+            let header: CommonHeader = bytemuck::pod_read_unaligned(&value[..expected]);
+            let cmd = header.cmd;
+            match CmdType::from(cmd) {
+                CmdType::None if cmd != CmdType::None as u32 => Err(DecodeError::UnknownOpcode(cmd)),
+                cmd_type if !cmd_type.is_supported() => Err(DecodeError::UnsupportedOpcode(cmd_type)),
+                _ => Ok(header),
+            }
+        }
+    }
+
+    /// Reasons a received datagram is dropped instead of being handled
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum DecodeError {
+        TooShort { expected: usize, actual: usize },
+        TooLong { expected: usize, actual: usize },
+        UnknownOpcode(u32),
+        /// the opcode is defined by the protocol, but not handled by this implementation
+        UnsupportedOpcode(CmdType),
+        /// the opcode does not belong to the packet type decoded
+        UnexpectedOpcode { expected: CmdType, actual: u32 },
+        /// a field holds a value outside of its valid range
+        InvalidField(&'static str),
+    }
+
+    /// Decode a header of type `T` from the start of `value` and check its opcode
+    fn decode_prefix<T: Pod>(value: &[u8], cmd: CmdType) -> Result<T, DecodeError> {
+        let actual = CommonHeader::try_from(value)?.cmd;
+        if actual != cmd as u32 {
+            return Err(DecodeError::UnexpectedOpcode { expected: cmd, actual });
+        }
+        let expected = std::mem::size_of::<T>();
+        if value.len() < expected {
+            return Err(DecodeError::TooShort { expected, actual: value.len() });
+        }
+        Ok(bytemuck::pod_read_unaligned(&value[..expected]))
+    }
+
+    /// Rights presented by a holder must be known bits and must not exceed the rights granted by the owner
+    fn validate_rights(rights: u8, granted_rights: u8) -> Result<(), DecodeError> {
+        let (rights, granted_rights) = match (Rights::from_bits(rights), Rights::from_bits(granted_rights)) {
+            (Some(rights), Some(granted_rights)) => (rights, granted_rights),
+            _ => return Err(DecodeError::InvalidField("rights")),
+        };
+        if !granted_rights.contains(rights) {
+            return Err(DecodeError::InvalidField("rights"));
+        }
+        Ok(())
+    }
+
+    /// Decode a fixed size packet of type `T`, trailing bytes are rejected
+    fn decode_header<T: Pod>(value: &[u8], cmd: CmdType) -> Result<T, DecodeError> {
+        let header = decode_prefix(value, cmd)?;
+        let expected = std::mem::size_of::<T>();
+        if value.len() > expected {
+            return Err(DecodeError::TooLong { expected, actual: value.len() });
         }
+        Ok(header)
     }
 
     #[repr(C, packed)]
@@ -215,10 +313,10 @@ pub mod tcap {
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<NOPRequestHeader>()]>> for NOPRequestHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<NOPRequestHeader>()]> {
+    impl From<NOPRequestHeader> for Box<[u8; std::mem::size_of::<NOPRequestHeader>()]> {
+        fn from(header: NOPRequestHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<NOPRequestHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -229,23 +327,36 @@ pub mod tcap {
         pub(crate) common: CommonHeader,
         pub(crate) number_of_conts: u8,
         pub(crate) continutaion_cap_ids: [CapID;4],
-        pub(crate) flags: u8
+        pub(crate) flags: u8,
+        /// rights of the invoker
+        pub(crate) rights: u8,
+        /// rights granted by the owner and covered by the MAC, a superset of `rights`
+        pub(crate) granted_rights: u8,
+        /// owner epoch the capability was minted in
+        pub(crate) epoch: u32,
+        /// MAC of the invocation keyed with the capability MAC, see `invocation_mac`
+        pub(crate) mac: CapMac,
+        /// per-peer invocation sequence number, used for replay protection
+        pub(crate) sequence: u64,
+        /// milliseconds since the unix epoch at the invoker
+        pub(crate) timestamp: u64,
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]>> for RequestInvokeHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]> {
+    impl From<RequestInvokeHeader> for Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]> {
+        fn from(header: RequestInvokeHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<RequestInvokeHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
 
     impl RequestInvokeHeader {
-        pub(crate) fn construct(cap: Capability, number_of_conts: u8, continutaion_cap_ids: [CapID; 4], flags: Flags) -> (u32, RequestInvokeHeader) {
-            let mut rng = rand::thread_rng();
-            let stream_id = rand::Rng::gen::<u32>(&mut rng);
-
-            (stream_id, RequestInvokeHeader {
+        /// Invocation of `cap`, only the first four continuations are carried in the header
+        pub(crate) fn construct(cap: &Capability, continuations: &[CapID], flags: Flags, sequence: u64, stream_id: u32) -> RequestInvokeHeader {
+            let mut continutaion_cap_ids: [CapID; 4] = [0; 4];
+            let carried = continuations.len().min(continutaion_cap_ids.len());
+            continutaion_cap_ids[..carried].copy_from_slice(&continuations[..carried]);
+            let mut header = RequestInvokeHeader {
                 common: CommonHeader {
                     size: std::mem::size_of::<RequestInvokeHeader>()
                         .try_into()
@@ -254,10 +365,25 @@ pub mod tcap {
                     cmd: CmdType::RequestInvoke as u32,
                     cap_id: cap.cap_id
                 },
-                number_of_conts,
+                number_of_conts: continuations.len() as u8,
                 continutaion_cap_ids,
-                flags: flags.bits()
-            })
+                flags: flags.bits(),
+                rights: cap.rights().bits(),
+                granted_rights: cap.granted_rights().bits(),
+                epoch: cap.epoch(),
+                mac: [0; 16],
+                sequence,
+                timestamp: now_ms()
+            };
+            header.mac = invocation_mac(&cap.mac(), &header.authenticated_bytes());
+            header
+        }
+
+        /// Bytes of the header covered by the invocation MAC, all fields but the MAC itself
+        pub(crate) fn authenticated_bytes(&self) -> Vec<u8> {
+            let mut header = *self;
+            header.mac = [0; 16];
+            bytemuck::bytes_of(&header).to_vec()
         }
     }
 
@@ -270,17 +396,19 @@ pub mod tcap {
         cap_id: CapID
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<CapInvalidHeader>()]>> for CapInvalidHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<CapInvalidHeader>()]> {
+    impl From<CapInvalidHeader> for Box<[u8; std::mem::size_of::<CapInvalidHeader>()]> {
+        fn from(header: CapInvalidHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<CapInvalidHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
 
-    impl From<Vec<u8>> for CapInvalidHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for CapInvalidHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapInvalid)
         }
     }
 
@@ -300,6 +428,15 @@ pub mod tcap {
         }
     }
 
+    /// response code of a successful invocation
+    pub(crate) const RESPONSE_OK: u64 = 0;
+    /// response code of an invocation whose handler failed
+    pub(crate) const RESPONSE_FAILED: u64 = 100;
+    /// response code of an invocation rejected before running the handler
+    pub(crate) const RESPONSE_REJECTED: u64 = 101;
+    /// response code of a delegation the delegatee rejected, as it holds its maximum number of received caps
+    pub(crate) const RESPONSE_CAP_TABLE_FULL: u64 = 102;
+
     #[repr(C, packed)]
     #[derive(Copy, Clone, Pod, Zeroable, Debug)]
     pub(crate) struct RequestResponseHeader {
@@ -325,23 +462,19 @@ pub mod tcap {
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<RequestResponseHeader>()]>> for RequestResponseHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<RequestResponseHeader>()]> {
+    impl From<RequestResponseHeader> for Box<[u8; std::mem::size_of::<RequestResponseHeader>()]> {
+        fn from(header: RequestResponseHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<RequestResponseHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
 
-    impl From<Vec<u8>> for RequestResponseHeader {
-        fn from(value: Vec<u8>) -> Self {
-            assert!(
-                value.len() >= std::mem::size_of::<Self>(),
-                "Vector of len {:?} not large enough to unmarshal ResponseHeader req len {:?}",
-                value.len(),
-                std::mem::size_of::<Self>()
-            );
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for RequestResponseHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::RequestResponse)
         }
     }
 
@@ -355,14 +488,35 @@ pub mod tcap {
         pub(crate) cap_type: u8,
         pub(crate) object_owner_ip_address: [u8; 4],
         pub(crate) object_owner_port: u16,
+        /// rights of the delegatee
+        pub(crate) rights: u8,
+        /// rights granted by the owner and covered by the MAC, a superset of `rights`
+        pub(crate) granted_rights: u8,
+        /// number of further delegations the delegatee may perform
+        pub(crate) delegation_depth: u8,
+        /// delegation depth of the delegating node, zero if it was not allowed to delegate
+        pub(crate) delegator_depth: u8,
+        /// node that delegated the capability, the owner or the holder that asked the owner to delegate it
+        pub(crate) delegator_ip_address: [u8; 4],
+        pub(crate) delegator_port: u16,
+        /// unix time in ms at which the owner revokes the delegation, zero if it is not leased
+        pub(crate) lease_expiry_ms: u64,
+        /// owner epoch the capability was minted in
+        pub(crate) epoch: u32,
+        pub(crate) mac: CapMac,
     }
 
     impl InsertCapHeader {
+        /// Insert of `cap` at the delegatee of `derivation`, granting exactly `rights` with `mac`
         pub fn construct(
             cap: &Capability,
-            delegatee: IpAddress,
-            owner: IpAddress,
+            derivation: &Derivation,
+            rights: Rights,
+            delegator_depth: u8,
+            lease_expiry_ms: u64,
+            mac: CapMac,
         ) -> InsertCapHeader {
+            let owner = cap.owner_address();
             let mut rng = rand::thread_rng();
             let stream_id = rand::Rng::gen::<u32>(&mut rng);
             InsertCapHeader {
@@ -372,55 +526,80 @@ pub mod tcap {
                     stream_id,
                     cap_id: cap.cap_id,
                 },
-                cap_owner_ip: delegatee.address,
-                cap_owner_port: delegatee.port,
+                cap_owner_ip: derivation.delegatee.address,
+                cap_owner_port: derivation.delegatee.port,
                 cap_id: cap.cap_id,
                 cap_type: cap.cap_type.into(),
                 object_owner_ip_address: owner.address,
-                object_owner_port: owner.port
+                object_owner_port: owner.port,
+                rights: rights.bits(),
+                granted_rights: rights.bits(),
+                delegation_depth: derivation.depth,
+                delegator_depth,
+                delegator_ip_address: derivation.delegator.address,
+                delegator_port: derivation.delegator.port,
+                lease_expiry_ms,
+                epoch: cap.epoch(),
+                mac
             }
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<InsertCapHeader>()]>> for InsertCapHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<InsertCapHeader>()]> {
+    impl From<InsertCapHeader> for Box<[u8; std::mem::size_of::<InsertCapHeader>()]> {
+        fn from(header: InsertCapHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<InsertCapHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
 
-    impl From<Vec<u8>> for InsertCapHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for InsertCapHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: InsertCapHeader = decode_header(value, CmdType::InsertCap)?;
+            if header.cap_type > CapType::Memory as u8 {
+                return Err(DecodeError::InvalidField("cap_type"));
+            }
+            validate_rights(header.rights, header.granted_rights)?;
+            Ok(header)
         }
     }
 
-    impl From<Vec<u8>> for RevokeCapHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for RevokeCapHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapRevoke)
         }
     }
 
-    impl From<Vec<u8>> for RequestInvokeHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for RequestInvokeHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: RequestInvokeHeader = decode_header(value, CmdType::RequestInvoke)?;
+            if header.number_of_conts > 4 {
+                return Err(DecodeError::InvalidField("number_of_conts"));
+            }
+            if Flags::from_bits(header.flags).is_none() {
+                return Err(DecodeError::InvalidField("flags"));
+            }
+            validate_rights(header.rights, header.granted_rights)?;
+            Ok(header)
         }
     }
 
     #[repr(C, packed)]
     #[derive(Copy, Clone, Pod, Zeroable, Debug)]
     pub(crate) struct RevokeCapHeader {
-        common: CommonHeader,
+        pub(crate) common: CommonHeader,
         pub cap_owner_ip: IpAddress,
         pub cap_id: CapID,
     }
 
     impl RevokeCapHeader {
-        pub fn construct(cap: &Capability, owner: IpAddress) -> RevokeCapHeader {
-            let mut rng = rand::thread_rng();
-            let stream_id = rand::Rng::gen::<u32>(&mut rng);
-
+        pub fn construct(cap: &Capability, owner: IpAddress, stream_id: u32) -> RevokeCapHeader {
             RevokeCapHeader {
                 common: CommonHeader {
                     size: 0,
@@ -434,10 +613,162 @@ pub mod tcap {
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<RevokeCapHeader>()]>> for RevokeCapHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<RevokeCapHeader>()]> {
+    impl From<RevokeCapHeader> for Box<[u8; std::mem::size_of::<RevokeCapHeader>()]> {
+        fn from(header: RevokeCapHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<RevokeCapHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    /// Answer to requests presenting a capability minted in an earlier epoch of the owner
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct StaleEpochHeader {
+        pub(crate) common: CommonHeader,
+        /// current epoch of the owner
+        pub(crate) epoch: u32,
+    }
+
+    impl StaleEpochHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32, epoch: u32) -> StaleEpochHeader {
+            StaleEpochHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<StaleEpochHeader>() as u64,
+                    cmd: CmdType::CapStaleEpoch as u32,
+                    stream_id,
+                    cap_id,
+                },
+                epoch,
+            }
+        }
+    }
+
+    impl TryFrom<&[u8]> for StaleEpochHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapStaleEpoch)
+        }
+    }
+
+    impl From<StaleEpochHeader> for Box<[u8; std::mem::size_of::<StaleEpochHeader>()]> {
+        fn from(header: StaleEpochHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<StaleEpochHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    /// Sent to the delegator of an `InsertCapHeader` the delegatee rejected, because it holds the maximum number of received caps
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct CapTableFullHeader {
+        pub(crate) common: CommonHeader,
+        /// maximum number of received caps of the delegatee
+        pub(crate) capacity: u64,
+    }
+
+    impl CapTableFullHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32, capacity: u64) -> CapTableFullHeader {
+            CapTableFullHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<CapTableFullHeader>() as u64,
+                    cmd: CmdType::CapTableFull as u32,
+                    stream_id,
+                    cap_id,
+                },
+                capacity,
+            }
+        }
+    }
+
+    impl TryFrom<&[u8]> for CapTableFullHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapTableFull)
+        }
+    }
+
+    impl From<CapTableFullHeader> for Box<[u8; std::mem::size_of::<CapTableFullHeader>()]> {
+        fn from(header: CapTableFullHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<CapTableFullHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    /// Sent by a holder to the owner when it drops its copy of a capability
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct CapCloseHeader {
+        pub(crate) common: CommonHeader,
+    }
+
+    impl CapCloseHeader {
+        pub(crate) fn construct(cap_id: CapID) -> CapCloseHeader {
+            let mut rng = rand::thread_rng();
+            let stream_id = rand::Rng::gen::<u32>(&mut rng);
+            CapCloseHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<CapCloseHeader>() as u64,
+                    cmd: CmdType::CapClose as u32,
+                    stream_id,
+                    cap_id,
+                },
+            }
+        }
+    }
+
+    impl TryFrom<&[u8]> for CapCloseHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapClose)
+        }
+    }
+
+    impl From<CapCloseHeader> for Box<[u8; std::mem::size_of::<CapCloseHeader>()]> {
+        fn from(header: CapCloseHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<CapCloseHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    /// Acknowledgement of a `RevokeCapHeader`, sent on its stream even if the cap was already revoked
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct RevokeAckHeader {
+        pub(crate) common: CommonHeader,
+    }
+
+    impl RevokeAckHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32) -> RevokeAckHeader {
+            RevokeAckHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<RevokeAckHeader>() as u64,
+                    cmd: CmdType::CapRevokeAck as u32,
+                    stream_id,
+                    cap_id,
+                },
+            }
+        }
+    }
+
+    impl TryFrom<&[u8]> for RevokeAckHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::CapRevokeAck)
+        }
+    }
+
+    impl From<RevokeAckHeader> for Box<[u8; std::mem::size_of::<RevokeAckHeader>()]> {
+        fn from(header: RevokeAckHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<RevokeAckHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -448,10 +779,10 @@ pub mod tcap {
         pub(crate) common: CommonHeader
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<ControllerStartTimerHeader>()]>> for ControllerStartTimerHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<ControllerStartTimerHeader>()]> {
+    impl From<ControllerStartTimerHeader> for Box<[u8; std::mem::size_of::<ControllerStartTimerHeader>()]> {
+        fn from(header: ControllerStartTimerHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<ControllerStartTimerHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -478,10 +809,10 @@ pub mod tcap {
         pub(crate) common: CommonHeader
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<ControllerStopTimerHeader>()]>> for ControllerStopTimerHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<ControllerStopTimerHeader>()]> {
+    impl From<ControllerStopTimerHeader> for Box<[u8; std::mem::size_of::<ControllerStopTimerHeader>()]> {
+        fn from(header: ControllerStopTimerHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<ControllerStopTimerHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -508,10 +839,10 @@ pub mod tcap {
         pub(crate) common: CommonHeader
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<ControllerResetSwitchHeader>()]>> for ControllerResetSwitchHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<ControllerResetSwitchHeader>()]> {
+    impl From<ControllerResetSwitchHeader> for Box<[u8; std::mem::size_of::<ControllerResetSwitchHeader>()]> {
+        fn from(header: ControllerResetSwitchHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<ControllerResetSwitchHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -537,16 +868,18 @@ pub mod tcap {
         pub(crate) common: CommonHeader
     }
 
-    impl From<Vec<u8>> for ControllerStopHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for ControllerStopHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::ControllerStop)
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<ControllerStopHeader>()]>> for ControllerStopHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<ControllerStopHeader>()]> {
+    impl From<ControllerStopHeader> for Box<[u8; std::mem::size_of::<ControllerStopHeader>()]> {
+        fn from(header: ControllerStopHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<ControllerStopHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
@@ -571,37 +904,50 @@ pub mod tcap {
     #[repr(C, packed)]
     #[derive(Copy, Clone, Pod, Zeroable, Debug)]
     pub(crate) struct MemoryCopyRequestHeader {
-        pub(crate) common: CommonHeader
+        pub(crate) common: CommonHeader,
+        /// chunk size requested by the receiver, the owner may choose a smaller one
+        pub(crate) chunk_size: u32,
+        pub(crate) rights: u8,
+        pub(crate) granted_rights: u8,
+        /// owner epoch the capability was minted in
+        pub(crate) epoch: u32,
+        pub(crate) mac: CapMac,
     }
 
 
-    impl From<Vec<u8>> for MemoryCopyRequestHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for MemoryCopyRequestHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: MemoryCopyRequestHeader = decode_header(value, CmdType::MemoryCopy)?;
+            validate_rights(header.rights, header.granted_rights)?;
+            Ok(header)
         }
     }
 
     
-    impl Into<Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]>> for MemoryCopyRequestHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]> {
+    impl From<MemoryCopyRequestHeader> for Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]> {
+        fn from(header: MemoryCopyRequestHeader) -> Self {
             let bytes: [u8; std::mem::size_of::<MemoryCopyRequestHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
+                unsafe { std::mem::transmute_copy(&header) };
             Box::new(bytes)
         }
     }
     impl MemoryCopyRequestHeader {
-        pub fn construct(cap_id: CapID) -> (u32, MemoryCopyRequestHeader) {
-            let mut rng = rand::thread_rng();
-            let stream_id = rand::Rng::gen::<u32>(&mut rng);
-
-            (stream_id, MemoryCopyRequestHeader {
+        pub fn construct(cap_id: CapID, stream_id: u32, chunk_size: u32, rights: Rights, granted_rights: Rights, epoch: u32, mac: CapMac) -> MemoryCopyRequestHeader {
+            MemoryCopyRequestHeader {
                 common: CommonHeader {
                     size: std::mem::size_of::<MemoryCopyRequestHeader>() as u64,
                     cmd: CmdType::MemoryCopy as u32,
                     stream_id,
-                    cap_id: cap_id,
-                }
-            })
+                    cap_id,
+                },
+                chunk_size,
+                rights: rights.bits(),
+                granted_rights: granted_rights.bits(),
+                epoch,
+                mac
+            }
         }
     }
 
@@ -612,78 +958,269 @@ pub mod tcap {
         pub(crate) size: u64,
         pub(crate) buf_size: u64,
         pub(crate) sequence: u32,
-        pub(crate) buffer: [u8; MEMCOPY_BUFFER_SIZE]
+        pub(crate) chunk_size: u32,
+    }
+
+    /// A memory copy chunk: the response header followed by `header.size` bytes of payload
+    #[derive(Clone, Debug)]
+    pub(crate) struct MemoryCopyResponse {
+        pub(crate) header: MemoryCopyResponseHeader,
+        pub(crate) buffer: Vec<u8>,
     }
 
-    impl From<Vec<u8>> for MemoryCopyResponseHeader {
-        fn from(value: Vec<u8>) -> Self {
-            *bytemuck::from_bytes(&value)
+    impl TryFrom<&[u8]> for MemoryCopyResponse {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: MemoryCopyResponseHeader = decode_prefix(value, CmdType::MemoryCopyResponse)?;
+            let buffer = &value[std::mem::size_of::<MemoryCopyResponseHeader>()..];
+            if header.size != buffer.len() as u64 || header.size > MEMCOPY_MAX_CHUNK_SIZE as u64 {
+                return Err(DecodeError::InvalidField("size"));
+            }
+            if header.size > header.buf_size {
+                return Err(DecodeError::InvalidField("buf_size"));
+            }
+            if header.sequence == 0 {
+                return Err(DecodeError::InvalidField("sequence"));
+            }
+            Ok(MemoryCopyResponse { header, buffer: buffer.to_vec() })
         }
     }
 
-    impl Into<Box<[u8; std::mem::size_of::<MemoryCopyResponseHeader>()]>> for MemoryCopyResponseHeader {
-        fn into(self) -> Box<[u8; std::mem::size_of::<MemoryCopyResponseHeader>()]> {
-            let bytes: [u8; std::mem::size_of::<MemoryCopyResponseHeader>()] =
-                unsafe { std::mem::transmute_copy(&self) };
-            Box::new(bytes)
+    impl From<MemoryCopyResponse> for Box<[u8]> {
+        fn from(response: MemoryCopyResponse) -> Self {
+            let mut bytes = bytemuck::bytes_of(&response.header).to_vec();
+            bytes.extend_from_slice(&response.buffer);
+            bytes.into_boxed_slice()
         }
     }
-    impl MemoryCopyResponseHeader {
-        pub(crate) async fn construct(obj: Arc<Mutex<MemoryObject>>, cap_id: CapID, stream_id: u32) -> Vec<MemoryCopyResponseHeader> {
+
+    impl MemoryCopyResponse {
+        pub(crate) async fn construct(obj: Arc<Mutex<MemoryObject>>, cap_id: CapID, stream_id: u32, chunk_size: usize) -> Vec<MemoryCopyResponse> {
             let buffer = obj.lock().await.data.clone();
+            let chunk_size = chunk_size.clamp(1, MEMCOPY_MAX_CHUNK_SIZE);
+
+            let mut chunks: Vec<&[u8]> = buffer.chunks(chunk_size).collect();
+            if chunks.is_empty() {
+                // empty objects are transferred as a single empty chunk
+                chunks.push(&[]);
+            }
 
-            let mut sequence: u32 = 1;
-            let mut packets: Vec<MemoryCopyResponseHeader> = vec![]; 
-            let mut rest: &[u8] = buffer.as_slice();
-            let mut to_transfer = rest.len() as i64;
-            while to_transfer > 0 {
-                match rest.split_first_chunk::<MEMCOPY_BUFFER_SIZE>() {
-                    Some ((chunk, buf)) => {
-                        rest = buf;
-                        packets.push(MemoryCopyResponseHeader {
-                            common: CommonHeader {
-                                size: std::mem::size_of::<MemoryCopyResponseHeader>() as u64,
-                                cmd: CmdType::MemoryCopyResponse as u32,
-                                stream_id,
-                                cap_id,
-                            },
-                        size: chunk.len() as u64, sequence,
-                        buf_size: buffer.len()  as u64,
-                        buffer: *chunk
-                        });
-
-                    }
-                    None => {
-                        let mut buf = [0 as u8; MEMCOPY_BUFFER_SIZE];
-                        buf[..rest.len()].copy_from_slice(rest);
-                        packets.push(MemoryCopyResponseHeader {
-                            common: CommonHeader {
-                                size: std::mem::size_of::<MemoryCopyResponseHeader>() as u64,
-                                cmd: CmdType::MemoryCopyResponse as u32,
-                                stream_id,
-                                cap_id,
-                            },
-                            buf_size: buffer.len() as u64,
-                        size: buf.len() as u64, sequence,
-                        buffer: buf
-                        });
+            chunks.into_iter().zip(1..).map(|(chunk, sequence)| {
+                MemoryCopyResponse {
+                    header: MemoryCopyResponseHeader {
+                        common: CommonHeader {
+                            size: (std::mem::size_of::<MemoryCopyResponseHeader>() + chunk.len()) as u64,
+                            cmd: CmdType::MemoryCopyResponse as u32,
+                            stream_id,
+                            cap_id,
+                        },
+                        size: chunk.len() as u64,
+                        buf_size: buffer.len() as u64,
+                        sequence,
+                        chunk_size: chunk_size as u32,
                     },
-                };
-                sequence += 1;
-                to_transfer -= MEMCOPY_BUFFER_SIZE as i64;
+                    buffer: chunk.to_vec(),
+                }
+            }).collect()
+        }
+    }
+
+    // Leases
+
+    /// Request of a lease holder to extend its lease, sent to the owner
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct LeaseRenewHeader {
+        pub(crate) common: CommonHeader,
+        /// requested lease duration, counted from the receipt of the request
+        pub(crate) duration_ms: u64,
+    }
+
+    impl TryFrom<&[u8]> for LeaseRenewHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: LeaseRenewHeader = decode_header(value, CmdType::LeaseRenew)?;
+            if header.duration_ms == 0 {
+                return Err(DecodeError::InvalidField("duration_ms"));
+            }
+            Ok(header)
+        }
+    }
+
+    impl From<LeaseRenewHeader> for Box<[u8; std::mem::size_of::<LeaseRenewHeader>()]> {
+        fn from(header: LeaseRenewHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<LeaseRenewHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    impl LeaseRenewHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32, duration_ms: u64) -> LeaseRenewHeader {
+            LeaseRenewHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<LeaseRenewHeader>() as u64,
+                    cmd: CmdType::LeaseRenew as u32,
+                    stream_id,
+                    cap_id,
+                },
+                duration_ms
             }
+        }
+    }
 
-            packets
+    /// Answer of the owner to a `LeaseRenewHeader`, unknown or expired leases are answered with `CapInvalid`
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct LeaseRenewResponseHeader {
+        pub(crate) common: CommonHeader,
+        /// unix time in ms of the new expiry
+        pub(crate) lease_expiry_ms: u64,
+    }
+
+    impl TryFrom<&[u8]> for LeaseRenewResponseHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::LeaseRenewResponse)
+        }
+    }
+
+    impl From<LeaseRenewResponseHeader> for Box<[u8; std::mem::size_of::<LeaseRenewResponseHeader>()]> {
+        fn from(header: LeaseRenewResponseHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<LeaseRenewResponseHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    impl LeaseRenewResponseHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32, lease_expiry_ms: u64) -> LeaseRenewResponseHeader {
+            LeaseRenewResponseHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<LeaseRenewResponseHeader>() as u64,
+                    cmd: CmdType::LeaseRenewResponse as u32,
+                    stream_id,
+                    cap_id,
+                },
+                lease_expiry_ms
+            }
+        }
+    }
+
+    /// Sent to the owner when a delegatee delegates a capability further, the sender of the notice is the delegator.
+    /// The owner checks the delegator's MAC, mints the MAC for the requested rights and inserts the cap at the delegatee,
+    /// then answers with a `RequestResponse` on the notice's stream
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct DelegationNoticeHeader {
+        pub(crate) common: CommonHeader,
+        pub(crate) delegatee_ip: [u8; 4],
+        pub(crate) delegatee_port: u16,
+        /// rights requested for the delegatee
+        pub(crate) rights: u8,
+        /// rights granted to the delegator and covered by its MAC, a superset of `rights`
+        pub(crate) granted_rights: u8,
+        /// number of further delegations requested for the delegatee
+        pub(crate) delegation_depth: u8,
+        /// owner epoch the delegator's copy was minted in
+        pub(crate) epoch: u32,
+        /// MAC of the delegator's copy
+        pub(crate) mac: CapMac,
+    }
+
+    impl TryFrom<&[u8]> for DelegationNoticeHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            let header: DelegationNoticeHeader = decode_header(value, CmdType::DelegationNotice)?;
+            validate_rights(header.rights, header.granted_rights)?;
+            Ok(header)
+        }
+    }
+
+    impl From<DelegationNoticeHeader> for Box<[u8; std::mem::size_of::<DelegationNoticeHeader>()]> {
+        fn from(header: DelegationNoticeHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<DelegationNoticeHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    impl DelegationNoticeHeader {
+        pub(crate) fn construct(
+            cap: &Capability,
+            stream_id: u32,
+            delegatee: IpAddress,
+            rights: Rights,
+            granted_rights: Rights,
+            delegation_depth: u8,
+            mac: CapMac,
+        ) -> DelegationNoticeHeader {
+            DelegationNoticeHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<DelegationNoticeHeader>() as u64,
+                    cmd: CmdType::DelegationNotice as u32,
+                    stream_id,
+                    cap_id: cap.cap_id,
+                },
+                delegatee_ip: delegatee.address,
+                delegatee_port: delegatee.port,
+                rights: rights.bits(),
+                granted_rights: granted_rights.bits(),
+                delegation_depth,
+                epoch: cap.epoch(),
+                mac,
+            }
+        }
+    }
+
+    #[repr(C, packed)]
+    #[derive(Copy, Clone, Pod, Zeroable, Debug)]
+    pub(crate) struct MemoryCopyAckHeader {
+        pub(crate) common: CommonHeader,
+        pub(crate) sequence: u32,
+    }
+
+    impl TryFrom<&[u8]> for MemoryCopyAckHeader {
+        type Error = DecodeError;
+
+        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+            decode_header(value, CmdType::MemoryCopyAck)
+        }
+    }
+
+    impl From<MemoryCopyAckHeader> for Box<[u8; std::mem::size_of::<MemoryCopyAckHeader>()]> {
+        fn from(header: MemoryCopyAckHeader) -> Self {
+            let bytes: [u8; std::mem::size_of::<MemoryCopyAckHeader>()] =
+                unsafe { std::mem::transmute_copy(&header) };
+            Box::new(bytes)
+        }
+    }
+
+    impl MemoryCopyAckHeader {
+        pub(crate) fn construct(cap_id: CapID, stream_id: u32, sequence: u32) -> MemoryCopyAckHeader {
+            MemoryCopyAckHeader {
+                common: CommonHeader {
+                    size: std::mem::size_of::<MemoryCopyAckHeader>() as u64,
+                    cmd: CmdType::MemoryCopyAck as u32,
+                    stream_id,
+                    cap_id,
+                },
+                sequence
+            }
         }
     }
 
     mod tests {
         #![allow(unused_imports)] // Not sure, why the import is detected as unused.
         use crate::packet_types::tcap::IpAddress;
-        use super::MemoryCopyResponseHeader;
+        use super::{CmdType, CommonHeader, DecodeError, Flags, MemoryCopyResponse, MemoryCopyResponseHeader, RequestInvokeHeader, RequestResponseHeader};
         use tokio::sync::Mutex;
         use std::sync::Arc;
-        use crate::{capabilities::tcap::CapID, object::tcap::object::MemoryObject, MEMCOPY_BUFFER_SIZE};
+        use crate::{capabilities::tcap::{CapID, Rights}, object::tcap::object::MemoryObject, MEMCOPY_BUFFER_SIZE};
         #[test]
         fn test_create_ip_addr_object_from_string() {
             let obj = IpAddress::from("10.0.0.1:1234");
@@ -706,27 +1243,99 @@ pub mod tcap {
             const CAP_ID: CapID = 1234;
             const STREAM_ID: u32 = 98232;
 
-            let buffer = Vec::from([0 as u8;BUF_SIZE]);
+            let buffer: Vec<u8> = (0..BUF_SIZE).map(|i| i as u8).collect();
             let object = Arc::new(Mutex::new(MemoryObject::new(buffer).await));
 
-            let packets = MemoryCopyResponseHeader::construct(object.clone(), CAP_ID, STREAM_ID).await;
+            let packets = MemoryCopyResponse::construct(object.clone(), CAP_ID, STREAM_ID, MEMCOPY_BUFFER_SIZE).await;
 
             assert!(packets.len() == BUF_SIZE.div_ceil(MEMCOPY_BUFFER_SIZE), "number of packets must be ceil(buf_size/copy_buffer_size) packet len is {:?}", packets.len());
 
-            let mut cur_sequence = 1;
+            let mut cur_sequence: u32 = 1;
             for packet in packets {
-                assert!(packet.common.cap_id == CAP_ID, "cap id must be the same for every packet");
-                assert!(packet.common.stream_id == STREAM_ID, "stream id must be the same for every packet");
-                assert!(packet.sequence == cur_sequence, "sequence number must be correct");
-                assert!(packet.buf_size == BUF_SIZE as u64, "complete buf size must be correct");
-                
-                for i in 0..MEMCOPY_BUFFER_SIZE {
-                    let index = i + packet.size.min(MEMCOPY_BUFFER_SIZE as u64 * (cur_sequence -1)) as usize;
-                    assert!(packet.buffer[i] == object.lock().await.data[index], "buffer mismatch");
+                let header = packet.header;
+                assert!(header.common.cap_id == CAP_ID, "cap id must be the same for every packet");
+                assert!(header.common.stream_id == STREAM_ID, "stream id must be the same for every packet");
+                assert!(header.sequence == cur_sequence, "sequence number must be correct");
+                assert!(header.buf_size == BUF_SIZE as u64, "complete buf size must be correct");
+                assert!(header.size == packet.buffer.len() as u64, "size must match the payload length");
+
+                let offset = MEMCOPY_BUFFER_SIZE * (cur_sequence as usize - 1);
+                for i in 0..packet.buffer.len() {
+                    assert!(packet.buffer[i] == object.lock().await.data[offset + i], "buffer mismatch");
                 }
 
                 cur_sequence += 1;
             }
         }
+
+        #[tokio::test]
+        async fn test_mempacket_chunk_size() {
+            const BUF_SIZE: usize = 20000;
+            const CHUNK_SIZE: usize = 8900;
+
+            let buffer: Vec<u8> = (0..BUF_SIZE).map(|i| (i % 251) as u8).collect();
+            let object = Arc::new(Mutex::new(MemoryObject::new(buffer.clone()).await));
+
+            let packets = MemoryCopyResponse::construct(object, 1, 1, CHUNK_SIZE).await;
+            assert!(packets.len() == BUF_SIZE.div_ceil(CHUNK_SIZE));
+
+            let mut received = vec![];
+            for packet in packets {
+                assert!(packet.header.chunk_size == CHUNK_SIZE as u32, "negotiated chunk size must be announced");
+                let bytes: Box<[u8]> = packet.into();
+                assert!(bytes.len() <= std::mem::size_of::<MemoryCopyResponseHeader>() + CHUNK_SIZE);
+                let decoded = MemoryCopyResponse::try_from(&bytes[..]).unwrap();
+                received.extend(decoded.buffer);
+            }
+            assert!(received == buffer, "chunks must reassemble to the original buffer");
+        }
+
+        #[test]
+        fn test_decode_malformed_packets() {
+            let header = RequestResponseHeader {
+                common: CommonHeader { size: 0, stream_id: 1, cmd: CmdType::RequestResponse as u32, cap_id: 42 },
+                response_code: 0,
+            };
+            let bytes = bytemuck::bytes_of(&header).to_vec();
+            assert!(RequestResponseHeader::try_from(&bytes[..]).is_ok());
+
+            assert!(matches!(CommonHeader::try_from(&bytes[..3]), Err(DecodeError::TooShort { .. })));
+            assert!(matches!(RequestResponseHeader::try_from(&bytes[..bytes.len() - 1]), Err(DecodeError::TooShort { .. })));
+            let mut long = bytes.clone();
+            long.push(0);
+            assert!(matches!(RequestResponseHeader::try_from(&long[..]), Err(DecodeError::TooLong { .. })));
+
+            let mut unknown = bytes.clone();
+            unknown[12..16].copy_from_slice(&4u32.to_ne_bytes());
+            assert!(matches!(CommonHeader::try_from(&unknown[..]), Err(DecodeError::UnknownOpcode(4))));
+            for unsupported in [CmdType::Nop, CmdType::CapGetInfo, CmdType::CapIsSame, CmdType::CapDiminish, CmdType::RequestCreate, CmdType::RequestReceive, CmdType::None] {
+                unknown[12..16].copy_from_slice(&(unsupported as u32).to_ne_bytes());
+                assert!(matches!(CommonHeader::try_from(&unknown[..]), Err(DecodeError::UnsupportedOpcode(cmd)) if cmd == unsupported));
+            }
+            assert!(matches!(MemoryCopyResponse::try_from(&bytes[..]), Err(DecodeError::UnexpectedOpcode { .. })));
+        }
+
+        #[tokio::test]
+        async fn test_decode_invalid_fields() {
+            let object = Arc::new(Mutex::new(MemoryObject::new(vec![1; 100]).await));
+            let packet = MemoryCopyResponse::construct(object, 1, 1, 64).await.remove(0);
+            let bytes: Box<[u8]> = packet.into();
+            assert!(MemoryCopyResponse::try_from(&bytes[..]).is_ok());
+            assert!(matches!(MemoryCopyResponse::try_from(&bytes[..bytes.len() - 1]), Err(DecodeError::InvalidField("size"))),
+                "payload shorter than announced must be rejected");
+
+            let mut header: RequestInvokeHeader = bytemuck::Zeroable::zeroed();
+            header.common.cmd = CmdType::RequestInvoke as u32;
+            header.flags = Flags::REQUIRE_RESPONSE.bits() | 0x80;
+            let bytes = bytemuck::bytes_of(&header).to_vec();
+            assert!(matches!(RequestInvokeHeader::try_from(&bytes[..]), Err(DecodeError::InvalidField("flags"))));
+
+            header.flags = Flags::REQUIRE_RESPONSE.bits();
+            header.rights = (Rights::INVOKE | Rights::DELEGATE).bits();
+            header.granted_rights = Rights::INVOKE.bits();
+            let bytes = bytemuck::bytes_of(&header).to_vec();
+            assert!(matches!(RequestInvokeHeader::try_from(&bytes[..]), Err(DecodeError::InvalidField("rights"))),
+                "rights beyond the granted rights must be rejected");
+        }
     }
 }
@@ -0,0 +1,149 @@
+pub mod tcap {
+    pub(crate) mod rate_limit {
+        use std::{collections::HashMap, net::SocketAddr, sync::Mutex, time::Instant};
+
+        use crate::config::Config;
+
+        /// Number of buckets above which idle buckets are dropped
+        const MAX_IDLE_BUCKETS: usize = 4096;
+
+        #[derive(Clone, Copy, Debug)]
+        struct TokenBucket {
+            tokens: f64,
+            updated: Instant,
+        }
+
+        impl TokenBucket {
+            fn new(burst: f64, now: Instant) -> TokenBucket {
+                TokenBucket { tokens: burst, updated: now }
+            }
+
+            fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+                let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+                self.tokens = (self.tokens + elapsed * rate).min(burst);
+                self.updated = now;
+            }
+        }
+
+        /// Token bucket limits for unsolicited packets, per source and per source and opcode.
+        /// A packet is accepted only if all buckets it falls into hold a token.
+        #[derive(Debug)]
+        pub(crate) struct RateLimiter {
+            source_rate: Option<f64>,
+            opcode_rates: HashMap<u32, f64>,
+            burst: f64,
+            buckets: Mutex<HashMap<(SocketAddr, Option<u32>), TokenBucket>>,
+        }
+
+        impl RateLimiter {
+            pub(crate) fn new(config: &Config) -> RateLimiter {
+                RateLimiter {
+                    source_rate: config.source_rate_limit,
+                    opcode_rates: config.opcode_rate_limits.iter().cloned().collect(),
+                    burst: config.rate_limit_burst.max(1.0),
+                    buckets: Mutex::new(HashMap::new()),
+                }
+            }
+
+            pub(crate) fn is_enabled(&self) -> bool {
+                self.source_rate.is_some() || !self.opcode_rates.is_empty()
+            }
+
+            /// Take a token for a packet with opcode `cmd` from `source`, false if the packet has to be dropped
+            pub(crate) fn allow(&self, source: SocketAddr, cmd: u32) -> bool {
+                self.allow_at(source, cmd, Instant::now())
+            }
+
+            fn allow_at(&self, source: SocketAddr, cmd: u32, now: Instant) -> bool {
+                let limits = [
+                    (None, self.source_rate),
+                    (Some(cmd), self.opcode_rates.get(&cmd).cloned()),
+                ];
+
+                let mut buckets = self.buckets.lock().unwrap();
+                if buckets.len() > MAX_IDLE_BUCKETS {
+                    self.drop_idle_buckets(&mut buckets, now);
+                }
+
+                let mut allowed = true;
+                for (opcode, rate) in limits.iter() {
+                    if let Some(rate) = rate {
+                        let bucket = buckets
+                            .entry((source, *opcode))
+                            .or_insert_with(|| TokenBucket::new(self.burst, now));
+                        bucket.refill(*rate, self.burst, now);
+                        allowed &= bucket.tokens >= 1.0;
+                    }
+                }
+
+                if allowed {
+                    for (opcode, rate) in limits.iter() {
+                        if rate.is_some() {
+                            buckets.get_mut(&(source, *opcode)).unwrap().tokens -= 1.0;
+                        }
+                    }
+                }
+                allowed
+            }
+
+            /// Full buckets carry no state, they are recreated on the next packet of their source
+            fn drop_idle_buckets(&self, buckets: &mut HashMap<(SocketAddr, Option<u32>), TokenBucket>, now: Instant) {
+                buckets.retain(|(_, opcode), bucket| {
+                    let rate = match opcode {
+                        None => self.source_rate,
+                        Some(cmd) => self.opcode_rates.get(cmd).cloned(),
+                    };
+                    match rate {
+                        Some(rate) => {
+                            bucket.refill(rate, self.burst, now);
+                            bucket.tokens < self.burst
+                        }
+                        None => false,
+                    }
+                });
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use std::time::{Duration, Instant};
+
+            use clap::Parser;
+
+            use super::RateLimiter;
+            use crate::{config::Config, packet_types::tcap::CmdType};
+
+            #[test]
+            fn test_source_rate_limit() {
+                let limiter = RateLimiter::new(&Config::parse_from([
+                    "tcap", "-i", "lo", "-a", "127.0.0.1:1234", "-s", "127.0.0.1:1235",
+                    "--source-rate-limit", "10", "--rate-limit-burst", "2",
+                ]));
+                let source = "10.0.0.1:1234".parse().unwrap();
+                let other = "10.0.0.2:1234".parse().unwrap();
+                let cmd = CmdType::RequestInvoke as u32;
+                let now = Instant::now();
+
+                assert!(limiter.allow_at(source, cmd, now));
+                assert!(limiter.allow_at(source, cmd, now));
+                assert!(!limiter.allow_at(source, cmd, now), "burst must be exhausted");
+                assert!(limiter.allow_at(other, cmd, now), "sources must be limited independently");
+                assert!(limiter.allow_at(source, cmd, now + Duration::from_millis(200)), "bucket must refill at the configured rate");
+            }
+
+            #[test]
+            fn test_opcode_rate_limit() {
+                let limiter = RateLimiter::new(&Config::parse_from([
+                    "tcap", "-i", "lo", "-a", "127.0.0.1:1234", "-s", "127.0.0.1:1235",
+                    "--opcode-rate-limit", "14=1", "--rate-limit-burst", "1",
+                ]));
+                let source = "10.0.0.1:1234".parse().unwrap();
+                let now = Instant::now();
+
+                assert!(limiter.allow_at(source, CmdType::RequestInvoke as u32, now));
+                assert!(!limiter.allow_at(source, CmdType::RequestInvoke as u32, now));
+                assert!(limiter.allow_at(source, CmdType::MemoryCopy as u32, now), "opcodes without a limit must not be limited");
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+pub mod tcap {
+    pub(crate) mod replay {
+        use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+
+        /// Number of sequence numbers below the highest one seen that are still accepted
+        pub(crate) const REPLAY_WINDOW_SIZE: u64 = 64;
+
+        pub(crate) fn now_ms() -> u64 {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0)
+        }
+
+        /// First invocation sequence number for a peer.
+        /// Derived from the clock, so a restarted sender continues above the sequence numbers it used before.
+        pub(crate) fn initial_sequence() -> u64 {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(1)
+        }
+
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub(crate) enum ReplayCheck {
+            /// sequence number not seen before
+            Fresh,
+            /// sequence number already seen, with the response code of the first invocation if it finished
+            Duplicate(Option<u64>),
+            /// sequence number too far behind the highest one seen
+            Stale,
+        }
+
+        /// Sliding window of the invocation sequence numbers received from one peer
+        #[derive(Clone, Debug, Default)]
+        pub(crate) struct ReplayWindow {
+            highest: u64,
+            seen: u64,
+            responses: HashMap<u64, u64>,
+        }
+
+        impl ReplayWindow {
+            /// Check `sequence` and mark it as seen
+            pub(crate) fn check(&mut self, sequence: u64) -> ReplayCheck {
+                if sequence > self.highest {
+                    let shift = sequence - self.highest;
+                    self.seen = if shift >= REPLAY_WINDOW_SIZE { 0 } else { self.seen << shift };
+                    self.seen |= 1;
+                    self.highest = sequence;
+                    let highest = self.highest;
+                    self.responses.retain(|s, _| highest - s < REPLAY_WINDOW_SIZE);
+                    return ReplayCheck::Fresh;
+                }
+
+                let offset = self.highest - sequence;
+                if offset >= REPLAY_WINDOW_SIZE {
+                    return ReplayCheck::Stale;
+                }
+
+                if self.seen & (1 << offset) != 0 {
+                    return ReplayCheck::Duplicate(self.responses.get(&sequence).cloned());
+                }
+
+                self.seen |= 1 << offset;
+                ReplayCheck::Fresh
+            }
+
+            /// Remember the response to `sequence`, so retransmits are answered without running the handler again
+            pub(crate) fn record_response(&mut self, sequence: u64, response_code: u64) {
+                if self.highest - sequence.min(self.highest) < REPLAY_WINDOW_SIZE {
+                    self.responses.insert(sequence, response_code);
+                }
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use super::{ReplayCheck, ReplayWindow, REPLAY_WINDOW_SIZE};
+
+            #[test]
+            fn test_replay_window() {
+                let mut window = ReplayWindow::default();
+                assert!(window.check(100) == ReplayCheck::Fresh);
+                assert!(window.check(100) == ReplayCheck::Duplicate(None), "same sequence must be detected");
+                assert!(window.check(98) == ReplayCheck::Fresh, "reordered sequence within the window must be accepted");
+                assert!(window.check(98) == ReplayCheck::Duplicate(None));
+                assert!(window.check(100 - REPLAY_WINDOW_SIZE) == ReplayCheck::Stale, "sequence behind the window must be rejected");
+                assert!(window.check(100 + REPLAY_WINDOW_SIZE * 2) == ReplayCheck::Fresh);
+                assert!(window.check(100) == ReplayCheck::Stale);
+            }
+
+            #[test]
+            fn test_replay_window_cached_response() {
+                let mut window = ReplayWindow::default();
+                assert!(window.check(7) == ReplayCheck::Fresh);
+                window.record_response(7, 0);
+                assert!(window.check(7) == ReplayCheck::Duplicate(Some(0)), "retransmits must get the cached response");
+            }
+        }
+    }
+}
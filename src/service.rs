@@ -1,32 +1,81 @@
 pub mod tcap {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::ops::{AddAssign, MulAssign};
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
     use std::io;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
 
+    use crate::audit::tcap::audit::{AuditEvent, AuditLog, AuditOutcome, AuditRecord, AuditSink};
+    use crate::auth::tcap::auth::{verify_invocation_mac, CapMac, MacKey};
     use crate::cap_table::tcap::cap_table::CapTable;
-    use crate::capabilities::tcap::{Capability, CapType, CapID};
+    use crate::events::tcap::events::ServiceEvent;
+    use crate::object::tcap::object::{MemoryObject, RequestHandler};
+    use crate::capabilities::tcap::{is_valid_delegation_depth, Capability, CapType, CapID, Derivation, InvokeError, Rights, UNKNOWN_EPOCH};
     use crate::packet_types::tcap::*;
+    use crate::rate_limit::tcap::rate_limit::RateLimiter;
+    use crate::snapshot::tcap::snapshot::Snapshot;
+    use crate::tcap::HandlerParameters;
+    use crate::replay::tcap::replay::{initial_sequence, now_ms, ReplayCheck, ReplayWindow};
+    use crate::transport::tcap::transport::Transport;
     use crate::config::Config;
+    use crate::{MEMCOPY_ACK_TIMEOUT_MS, MEMCOPY_MAX_RETRANSMITS, RECV_BUFFER_SIZE};
     use log::{debug, error, info, warn};
-    use tokio::net::UdpSocket;
-    use tokio::sync::{mpsc, Mutex, Notify, Semaphore};
+    use tokio::sync::{broadcast, mpsc, Mutex, Notify, Semaphore};
     use core::fmt;
+
+    /// peer and stream id of a stream
+    type StreamKey = (String, u32);
+    /// responses keyed by peer, stream id and sequence number
+    type Responses = HashMap<(String, u32, u32), Response>;
+    /// callback registered with `Service::on_reclaim`
+    type ReclaimCallback = Box<dyn Fn(CapID, CapType) + Send + Sync>;
     
     #[derive(Clone)]
     pub struct Service {
         send_channel: Arc<Mutex<mpsc::Sender<SendRequest>>>,
         receiver: Arc<Mutex<mpsc::Receiver<SendRequest>>>,
         pub(crate) config: Config,
-        socket: Arc<UdpSocket>,
-        pub(crate) responses: Arc<Mutex<HashMap<u32, Response>>>,
-        response_notifiers: Arc<Mutex<HashMap<u32, Arc<Semaphore>>>>,
+        mac_key: Arc<MacKey>,
+        /// epoch of the service, caps minted in an earlier epoch are stale
+        epoch: Arc<AtomicU32>,
+        transport: Arc<Transport>,
+        audit_log: Option<Arc<AuditSink>>,
+        rate_limiter: Arc<RateLimiter>,
+        /// responses to outstanding streams, keyed by peer, stream id and sequence number
+        responses: Arc<Mutex<Responses>>,
+        /// notifiers of the streams this service waits on keyed by peer and stream id,
+        /// an entry exists exactly while the stream is open
+        response_notifiers: Arc<Mutex<HashMap<StreamKey, Arc<Semaphore>>>>,
+        /// memory copies served by this service, keyed by requesting peer and stream id
+        copy_windows: Arc<Mutex<HashMap<StreamKey, mpsc::UnboundedSender<u32>>>>,
+        invoke_sequences: Arc<Mutex<HashMap<String, u64>>>,
+        replay_windows: Arc<Mutex<HashMap<String, ReplayWindow>>>,
+        /// leases handed out by this service, keyed by cap id and delegatee
+        leases: Arc<Mutex<HashMap<(CapID, String), Lease>>>,
+        /// handlers request caps can be bound to by name
+        handlers: Arc<Mutex<HashMap<String, RequestHandler>>>,
+        /// called with the cap id and type when an owned capability without holders is reclaimed
+        reclaim_callback: Arc<Mutex<Option<ReclaimCallback>>>,
+        events: broadcast::Sender<ServiceEvent>,
         pub(crate) cap_table: CapTable,
         termination_notifier: Arc<Notify>,
+        shutting_down: Arc<AtomicBool>,
+        pending_sends: Arc<AtomicUsize>,
+        running_handlers: Arc<AtomicUsize>,
+        rejected_packets: Arc<AtomicUsize>,
+        failed_revocations: Arc<AtomicUsize>,
         #[cfg(feature="net-stats")]
         pub send_counter: Arc<Mutex<u128>>,
         #[cfg(feature="net-stats")]
-        pub recv_counter: Arc<Mutex<u128>>
+        pub recv_counter: Arc<Mutex<u128>>,
+        /// unsolicited packets dropped by the rate limits
+        #[cfg(feature="net-stats")]
+        pub rate_limited_counter: Arc<Mutex<u128>>,
+        /// received packets dropped because they failed to decode
+        #[cfg(feature="net-stats")]
+        pub malformed_counter: Arc<Mutex<u128>>
     }
 
     impl fmt::Debug for Service {
@@ -42,7 +91,6 @@ pub mod tcap {
         pub dest: String,
         pub data: Box<[u8]>,
         pub stream_id: u32,
-        response_notification: Arc<Semaphore>
     }
 
     impl SendRequest {
@@ -51,13 +99,13 @@ pub mod tcap {
                 data.len() >= std::mem::size_of::<CommonHeader>(),
                 "Packet must at keast contain the common header"
             );
-            let stream_id = CommonHeader::from(data[0..std::mem::size_of::<CommonHeader>()].to_vec()).stream_id;
-            let response_notification = Arc::new(Semaphore::new(0));
+            let stream_id = CommonHeader::try_from(&data[..])
+                .expect("outgoing packets must start with a valid common header")
+                .stream_id;
             Self {
                 dest,
                 data,
                 stream_id,
-                response_notification,
             }
         }
     }
@@ -68,80 +116,311 @@ pub mod tcap {
         pub data: Vec<u8>,
     }
 
+    /// Summary of the work that could not be completed by `Service::run` when shutting down
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct ShutdownReport {
+        /// packets still in the send queue when the shutdown deadline passed
+        pub dropped_packets: usize,
+        /// unsolicited packets rejected after the shutdown started
+        pub rejected_packets: usize,
+        /// packet handlers still running when the shutdown deadline passed
+        pub unfinished_handlers: usize,
+        /// revocations by `Service::terminate` that failed or panicked
+        pub failed_revocations: usize,
+    }
+
+    /// Counts a running packet handler for as long as it is alive
+    struct HandlerGuard(Arc<AtomicUsize>);
+
+    impl HandlerGuard {
+        fn new(counter: Arc<AtomicUsize>) -> Self {
+            counter.fetch_add(1, Ordering::SeqCst);
+            HandlerGuard(counter)
+        }
+    }
+
+    impl Drop for HandlerGuard {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Key of `peer` in the stream maps, the form in which received packets report their sender
+    fn peer_key(peer: &str) -> String {
+        match peer.parse::<SocketAddr>() {
+            Ok(address) => address.to_string(),
+            Err(_) => peer.to_string(),
+        }
+    }
+
+    /// Lease of a capability handed out by this service
+    #[derive(Clone, Copy, Debug)]
+    struct Lease {
+        /// unix time in ms at which the delegation is revoked
+        expiry_ms: u64,
+        /// duration the lease was granted for, the limit for renewals
+        duration_ms: u64,
+    }
+
+    impl Lease {
+        /// Expiry of the lease renewed at `now` for `requested` ms, never more than the granted duration
+        fn renewed_expiry(&self, now: u64, requested: u64) -> u64 {
+            now.saturating_add(requested.min(self.duration_ms))
+        }
+    }
+
+    /// Random epoch different from `previous` and `UNKNOWN_EPOCH`
+    fn new_epoch(previous: u32) -> u32 {
+        loop {
+            let epoch = rand::Rng::gen::<u32>(&mut rand::thread_rng());
+            if epoch != previous && epoch != UNKNOWN_EPOCH {
+                return epoch;
+            }
+        }
+    }
+
     impl Service {
-        pub async fn new(config: Config) -> Service {
+        pub async fn new(mut config: Config) -> Service {
             let (send_channel, receiver) = mpsc::channel::<SendRequest>(256);
-            debug!("Binding UDP Socket to {:?}", config.address);
-            let socket = Arc::new(UdpSocket::bind(config.address.clone())
+            // restored caps are only valid with the key that authenticated their delegations
+            let snapshot = config.cap_snapshot.as_ref().map(|path| Snapshot::load(path).expect("cannot read cap snapshot"));
+            let snapshot = snapshot.flatten();
+            let mac_key = Arc::new(match (config.mac_key.as_ref(), snapshot.as_ref()) {
+                (Some(key), _) => MacKey::from_hex(key).expect("invalid MAC key"),
+                (None, Some(snapshot)) => MacKey::from_hex(&snapshot.mac_key).expect("invalid MAC key in cap snapshot"),
+                (None, None) => MacKey::generate(),
+            });
+            // caps restored from a snapshot stay valid, so keep the epoch they were minted in
+            let epoch = Arc::new(AtomicU32::new(match snapshot.and_then(|snapshot| snapshot.epoch) {
+                Some(epoch) if epoch != UNKNOWN_EPOCH => epoch,
+                _ => new_epoch(UNKNOWN_EPOCH),
+            }));
+
+            let transport = Arc::new(Transport::bind(&config)
                 .await
                 .unwrap());
-            socket.bind_device(Some(config.interface.as_str().as_bytes())).unwrap();
+            // services bound to port 0 are reached at the port the socket was bound to
+            config.set_bound_port(transport.local_addr().unwrap().port());
+
+            let audit_log = config.audit_log.as_ref().map(|path| {
+                let log = AuditLog::open(path, config.audit_log_max_bytes, config.audit_log_files)
+                    .expect("cannot open audit log");
+                Arc::new(AuditSink::spawn(log, config.audit_log_queue_size))
+            });
+
+            let rate_limiter = Arc::new(RateLimiter::new(&config));
 
             let send_channel = Arc::new(Mutex::new(send_channel));
             let receiver = Arc::new(Mutex::new(receiver));
 
             let responses = Arc::new(Mutex::new(HashMap::new()));
             let response_notifiers = Arc::new(Mutex::new(HashMap::new()));
+            let copy_windows = Arc::new(Mutex::new(HashMap::new()));
 
-            let cap_table = CapTable::new().await;
+            let cap_table = CapTable::new(config.cap_table_shards).await;
             
+            let (events, _) = broadcast::channel(config.event_queue_size.max(1));
+
             let termination_notifier = Arc::new(Notify::new());
             Service {
                 send_channel,
                 receiver,
                 config,
-                socket,
+                mac_key,
+                epoch,
+                transport,
+                audit_log,
+                rate_limiter,
                 responses,
                 response_notifiers,
+                copy_windows,
+                invoke_sequences: Arc::new(Mutex::new(HashMap::new())),
+                replay_windows: Arc::new(Mutex::new(HashMap::new())),
+                leases: Arc::new(Mutex::new(HashMap::new())),
+                handlers: Arc::new(Mutex::new(HashMap::new())),
+                reclaim_callback: Arc::new(Mutex::new(None)),
+                events,
                 cap_table,
                 termination_notifier,
+                shutting_down: Arc::new(AtomicBool::new(false)),
+                pending_sends: Arc::new(AtomicUsize::new(0)),
+                running_handlers: Arc::new(AtomicUsize::new(0)),
+                rejected_packets: Arc::new(AtomicUsize::new(0)),
+                failed_revocations: Arc::new(AtomicUsize::new(0)),
                 #[cfg(feature="net-stats")]
                 send_counter: Arc::new(Mutex::new(0)),
                 #[cfg(feature="net-stats")]
-                recv_counter: Arc::new(Mutex::new(0))
+                recv_counter: Arc::new(Mutex::new(0)),
+                #[cfg(feature="net-stats")]
+                rate_limited_counter: Arc::new(Mutex::new(0)),
+                #[cfg(feature="net-stats")]
+                malformed_counter: Arc::new(Mutex::new(0))
             }
         }
 
         pub async fn reset(&self) {
+            let epoch = new_epoch(self.epoch());
+            self.epoch.store(epoch, Ordering::SeqCst);
+            debug!("reset service, new epoch {:?}", epoch);
             self.cap_table.reset().await;
             self.response_notifiers.lock().await.clear();
             self.responses.lock().await.clear();
+            self.leases.lock().await.clear();
             self.send_counter.lock().await.mul_assign(0);
             self.recv_counter.lock().await.mul_assign(0);
+            self.rate_limited_counter.lock().await.mul_assign(0);
+            self.malformed_counter.lock().await.mul_assign(0);
+        }
+
+        /// Epoch of the service, it changes on every reset.
+        /// Capabilities carry the epoch they were minted in, invocations of caps from an earlier epoch fail as stale
+        pub fn epoch(&self) -> u32 {
+            self.epoch.load(Ordering::SeqCst)
+        }
+
+        /// Address peers reach the service at, the owner address of its capabilities
+        pub fn address(&self) -> &str {
+            self.config.advertised_address()
         }
 
         pub fn get_compilation_commit() -> String {
             env!("GIT_HASH").to_string()
         }
 
+        /// Create an owned capability, panics if `Config::max_owned_caps` is reached, see `try_create_capability`
         pub async fn create_capability(&self) -> Arc<Mutex<Capability>> {
+            self.try_create_capability().await.unwrap()
+        }
+
+        /// Create an owned capability, fails if `Config::max_owned_caps` is reached
+        pub async fn try_create_capability(&self) -> io::Result<Arc<Mutex<Capability>>> {
             let c = Arc::new(Mutex::new(
                 Capability::create(Arc::new(self.clone())).await,
             ));
 
-            self.cap_table.insert(c.clone()).await;
+            self.insert_owned(c.clone()).await?;
+            self.audit(AuditEvent::Create, c.lock().await.cap_id, self.config.advertised_address(), AuditOutcome::Success);
 
-            c
+            Ok(c)
+        }
+
+        async fn insert_owned(&self, cap: Arc<Mutex<Capability>>) -> io::Result<()> {
+            if self.cap_table.try_insert(cap, self.config.max_owned_caps).await {
+                return Ok(());
+            }
+            Err(io::Error::other(format!(
+                "limit of {} owned capabilities reached",
+                self.config.max_owned_caps.unwrap_or_default()
+            )))
+        }
+
+        /// Insert a capability received from another service. If `Config::max_received_caps` is reached,
+        /// a received cap with an expired lease is evicted to make room. Returns false if the table is still full
+        async fn insert_received(&self, cap: Arc<Mutex<Capability>>) -> bool {
+            let max = self.config.max_received_caps;
+            // caps inserted concurrently can take the evicted cap's place, evict again until none is left
+            while !self.cap_table.try_insert(cap.clone(), max).await {
+                if !self.evict_expired_received().await {
+                    return false;
+                }
+            }
+            true
+        }
+
+        /// Close a received cap with an expired lease, false if there is none
+        async fn evict_expired_received(&self) -> bool {
+            let now = now_ms();
+            for (meta, cap) in self.cap_table.get_caps_with_meta().await {
+                if meta.owned || cap.lock().await.lease_expiry_ms().is_none_or(|expiry| expiry > now) {
+                    continue;
+                }
+                debug!("evicting cap {:?} with expired lease, {:?} received caps held", meta.cap_id, self.cap_table.received_count());
+                self.close_capability(cap).await;
+                self.publish(ServiceEvent::CapEvicted { cap_id: meta.cap_id });
+                return true;
+            }
+            false
+        }
+
+        /// Number of capabilities owned by this service, limited by `Config::max_owned_caps`
+        pub fn owned_capability_count(&self) -> usize {
+            self.cap_table.owned_count()
+        }
+
+        /// Number of capabilities held for other owners, limited by `Config::max_received_caps`
+        pub fn received_capability_count(&self) -> usize {
+            self.cap_table.received_count()
         }
 
         pub async fn cap_exists(&self, cap_id: CapID) -> bool {
             self.cap_table.contains(cap_id).await
         }
 
+        /// Handle of the capability with `cap_id`, if this service holds it
+        pub async fn get_capability(&self, cap_id: CapID) -> Option<Arc<Mutex<Capability>>> {
+            self.cap_table.get(cap_id).await
+        }
+
+        /// All capabilities held by this service, owned and delegated ones
+        pub async fn capabilities(&self) -> Vec<Arc<Mutex<Capability>>> {
+            self.cap_table.get_caps().await
+        }
+
+        /// Capabilities held by this service with type `cap_type`
+        pub async fn capabilities_of_type(&self, cap_type: CapType) -> Vec<Arc<Mutex<Capability>>> {
+            self.cap_table
+                .get_caps_with_meta()
+                .await
+                .into_iter()
+                .filter(|(meta, _)| meta.cap_type == cap_type)
+                .map(|(_, cap)| cap)
+                .collect()
+        }
+
+        /// Capabilities held by this service whose object is owned by `owner`
+        pub async fn capabilities_owned_by(&self, owner: &IpAddress) -> Vec<Arc<Mutex<Capability>>> {
+            self.cap_table
+                .get_caps_with_meta()
+                .await
+                .into_iter()
+                .filter(|(meta, _)| meta.owner_address.same_node(owner))
+                .map(|(_, cap)| cap)
+                .collect()
+        }
+
+        /// Capabilities this service delegated to `delegatee`
+        pub async fn capabilities_delegated_to(&self, delegatee: &IpAddress) -> Vec<Arc<Mutex<Capability>>> {
+            let mut caps = Vec::new();
+            for cap in self.cap_table.get_caps().await {
+                if cap.lock().await.delegatees().await.iter().any(|d| d.same_node(delegatee)) {
+                    caps.push(cap);
+                }
+            }
+            caps
+        }
+
         /*** This function create a capability with a predefined cap id
          * It is a work around, as there is no global name service or authentication broker
          * TODO (@jkrbs): Build name service or initial cap distribution system
          */
         pub async fn create_capability_with_id(&self, cap_id: CapID) -> Arc<Mutex<Capability>> {
+            self.try_create_capability_with_id(cap_id).await.unwrap()
+        }
+
+        /// Create an owned capability with a predefined cap id, fails if `Config::max_owned_caps` is reached
+        pub async fn try_create_capability_with_id(&self, cap_id: CapID) -> io::Result<Arc<Mutex<Capability>>> {
             let c = Arc::new(Mutex::new(
                 Capability::create_with_id(Arc::new(self.clone()), cap_id).await,
             ));
 
-            self.cap_table.insert(c.clone()).await;
+            self.insert_owned(c.clone()).await?;
+            self.audit(AuditEvent::Create, cap_id, self.config.advertised_address(), AuditOutcome::Success);
 
-            c
+            Ok(c)
         }
 
+        /// Create a capability owned by `owner` with a predefined cap id but without a MAC.
+        /// The owner only serves it with `Config::require_mac` disabled, use `create_remote_capability_with_mac` otherwise
         pub async fn create_remote_capability_with_id(&self, owner: String, cap_id: CapID) -> Arc<Mutex<Capability>> {
             let owner_address = IpAddress::from(owner.as_str());
             let c = Arc::new(Mutex::new(
@@ -153,27 +432,437 @@ pub mod tcap {
             c
         }
 
+        /// Create a capability owned by `owner` with a predefined cap id and the MAC the owner issued for it in `epoch`,
+        /// see `capability_mac` and `epoch`
+        pub async fn create_remote_capability_with_mac(&self, owner: String, cap_id: CapID, mac: CapMac, epoch: u32) -> Arc<Mutex<Capability>> {
+            let c = self.create_remote_capability_with_id(owner, cap_id).await;
+            c.lock().await.set_mac(mac, epoch);
+            c
+        }
+
+        /// Register a handler request caps can be bound to with `Capability::bind_handler`.
+        /// Handlers have to be registered before `restore` to rebind the restored caps
+        pub async fn register_handler(&self, name: &str, function: Box<dyn Fn(HandlerParameters) -> Result<(), ()> + Send + Sync>) {
+            assert!(
+                !name.is_empty() && !name.contains(char::is_whitespace),
+                "handler names must not be empty or contain whitespace, got {:?}", name
+            );
+            self.handlers.lock().await.insert(name.to_string(), Arc::from(function));
+        }
+
+        pub(crate) async fn handler(&self, name: &str) -> Option<RequestHandler> {
+            self.handlers.lock().await.get(name).cloned()
+        }
+
+        /// Write the owned caps to the configured snapshot file
+        pub async fn snapshot(&self) -> io::Result<()> {
+            let path = self.config.cap_snapshot.as_ref().ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no cap snapshot file configured",
+            ))?;
+            let mut caps = Vec::new();
+            for (meta, cap) in self.cap_table.get_caps_with_meta().await {
+                if meta.owned {
+                    caps.push(cap.lock().await.snapshot().await);
+                }
+            }
+            debug!("writing {:?} caps to snapshot {:?}", caps.len(), path);
+            Snapshot { mac_key: self.mac_key.to_hex(), epoch: Some(self.epoch()), caps }.store(path)
+        }
+
+        /// Restore the owned caps from the configured snapshot file, returns the number of restored caps.
+        /// Request caps are bound to the handlers registered under their name
+        pub async fn restore(&self) -> io::Result<usize> {
+            let path = self.config.cap_snapshot.as_ref().ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no cap snapshot file configured",
+            ))?;
+            let snapshot = match Snapshot::load(path)? {
+                Some(snapshot) => snapshot,
+                None => return Ok(0),
+            };
+            if snapshot.mac_key != self.mac_key.to_hex() {
+                warn!("cap snapshot {:?} was written with a different MAC key, delegated caps will not verify", path);
+            }
+
+            for saved in snapshot.caps.iter() {
+                let cap = self.try_create_capability_with_id(saved.cap_id).await?;
+                let mut cap = cap.lock().await;
+                match (saved.cap_type, saved.handler.as_ref(), saved.memory.as_ref()) {
+                    (CapType::Request, Some(handler), _) => {
+                        if cap.bind_handler(handler).await.is_err() {
+                            warn!("no handler registered as {:?}, restoring cap {:?} unbound", handler, saved.cap_id);
+                        }
+                    }
+                    (CapType::Memory, _, Some(memory)) => {
+                        cap.bind_mem(Arc::new(Mutex::new(MemoryObject::new(memory.clone()).await))).await;
+                    }
+                    (CapType::None, _, _) => {}
+                    _ => warn!("cap {:?} was bound to an object that is not persisted, restoring it unbound", saved.cap_id),
+                }
+                cap.restore_delegations(saved).await;
+            }
+            info!("restored {:?} caps from snapshot {:?}", snapshot.caps.len(), path);
+            Ok(snapshot.caps.len())
+        }
+
+        /// MAC of a capability owned by this service, to hand out together with predefined cap ids and the epoch.
+        /// It grants all rights and becomes invalid when the service is reset.
+        pub fn capability_mac(&self, cap_id: CapID) -> CapMac {
+            self.capability_mac_with_rights(cap_id, Rights::all())
+        }
+
+        /// MAC of a capability owned by this service, granting `rights`
+        pub(crate) fn capability_mac_with_rights(&self, cap_id: CapID, rights: Rights) -> CapMac {
+            self.mac_key.compute(cap_id, &IpAddress::from(self.config.advertised_address()), rights, self.epoch())
+        }
+
+        /// Receiver of the events of this service, it sees the events published after subscribing.
+        /// A subscriber that falls more than `Config::event_queue_size` events behind misses the oldest ones
+        pub fn subscribe(&self) -> broadcast::Receiver<ServiceEvent> {
+            self.events.subscribe()
+        }
+
+        fn publish(&self, event: ServiceEvent) {
+            // sending only fails without subscribers
+            let _ = self.events.send(event);
+        }
+
+        /// Queue a record for the audit log, if one is configured
+        pub(crate) fn audit(&self, event: AuditEvent, cap_id: CapID, peer: &str, outcome: AuditOutcome) {
+            if let Some(audit_log) = self.audit_log.as_ref() {
+                audit_log.record(AuditRecord::new(event, cap_id, peer, outcome));
+            }
+        }
+
+        /// Forget the delegation of `cap` that `delegatee` rejected, with its lease and its entry on the switch
+        pub(crate) async fn drop_rejected_delegation(&self, cap: &Capability, delegatee: &IpAddress) {
+            if !cap.remove_holder(delegatee).await {
+                return;
+            }
+            let peer = String::from(*delegatee);
+            self.leases.lock().await.remove(&(cap.cap_id, peer.clone()));
+            // the switch already got the delegation, remove its entry without blocking on the ack
+            let (c, s, node) = (cap.clone(), self.clone(), *delegatee);
+            tokio::spawn(async move { c.revoke_on_node(s, node).await });
+            self.audit(AuditEvent::Delegate, cap.cap_id, &peer, AuditOutcome::Rejected);
+            self.publish(ServiceEvent::DelegationRejected { cap_id: cap.cap_id, delegatee: peer });
+        }
+
+        /// Check the rate limits for a received packet.
+        /// Only unsolicited packets are limited, responses to open streams and acks of served memory copies are not.
+        async fn is_rate_limited(&self, common: &CommonHeader, sender: SocketAddr) -> bool {
+            if !self.rate_limiter.is_enabled() {
+                return false;
+            }
+            let stream_id = common.stream_id;
+            if self.response_notifiers.lock().await.contains_key(&(sender.to_string(), stream_id)) {
+                return false;
+            }
+            if CmdType::from(common.cmd) == CmdType::MemoryCopyAck
+                && self.copy_windows.lock().await.contains_key(&(sender.to_string(), stream_id)) {
+                return false;
+            }
+
+            let allowed = self.rate_limiter.allow(sender, common.cmd);
+            if !allowed {
+                debug!("rate limit exceeded, dropping packet with cmd {:?} from {:?}", { common.cmd }, sender);
+            }
+            !allowed
+        }
+
+        /// Drop a received datagram that failed to decode
+        async fn drop_malformed(&self, source: &str, error: DecodeError) {
+            debug!("dropping malformed packet from {:?}: {:?}", source, error);
+            #[cfg(feature="net-stats")]
+            self.malformed_counter.lock().await.add_assign(1);
+        }
+
+        /// Check the MAC presented for an owned capability, if MACs are required
+        fn verify_mac(&self, cap_id: CapID, granted_rights: Rights, epoch: u32, mac: &CapMac) -> bool {
+            if !self.config.require_mac {
+                return true;
+            }
+            let valid = self.mac_key.verify(cap_id, &IpAddress::from(self.config.advertised_address()), granted_rights, epoch, mac);
+            if !valid {
+                warn!("invalid MAC presented for cap {:?}", cap_id);
+            }
+            valid
+        }
+
+        /// Check the epoch presented for an owned capability, answers stale caps with a `CapStaleEpoch`.
+        /// Caps without a known epoch are only served when MACs are not required, the MAC covers the epoch otherwise
+        async fn verify_epoch(&self, cap_id: CapID, epoch: u32, source: &str, stream_id: u32) -> bool {
+            let current = self.epoch();
+            if epoch == current || (epoch == UNKNOWN_EPOCH && !self.config.require_mac) {
+                return true;
+            }
+            warn!("cap {:?} presented from epoch {:?}, current epoch is {:?}", cap_id, epoch, current);
+            let packet: Box<[u8; std::mem::size_of::<StaleEpochHeader>()]> =
+                StaleEpochHeader::construct(cap_id, stream_id, current).into();
+            self.send(SendRequest::new(source.to_string(), packet), false).await;
+            false
+        }
+
+        /// Check the MAC of an invocation, keyed with the capability MAC for the presented rights and epoch.
+        /// It authenticates the capability along with the sequence number and timestamp checked for freshness
+        fn verify_invocation_mac(&self, hdr: &RequestInvokeHeader) -> bool {
+            if !self.config.require_mac {
+                return true;
+            }
+            let granted_rights = Rights::from_bits_truncate(hdr.granted_rights);
+            let cap_mac = self.mac_key.compute(hdr.common.cap_id, &IpAddress::from(self.config.advertised_address()), granted_rights, hdr.epoch);
+            let valid = verify_invocation_mac(&cap_mac, &hdr.authenticated_bytes(), &hdr.mac);
+            if !valid {
+                warn!("invalid invocation MAC presented for cap {:?}", { hdr.common.cap_id });
+            }
+            valid
+        }
+
+        /// Check that the rights presented for an owned capability include `required`
+        fn has_rights(&self, cap_id: CapID, required: Rights, rights: u8) -> bool {
+            let rights = Rights::from_bits_truncate(rights);
+            if !rights.contains(required) {
+                warn!("cap {:?} presented with rights {:?}, {:?} required", cap_id, rights, required);
+                return false;
+            }
+            true
+        }
+
+        /// Check that the rights presented for an owned capability include `required`.
+        /// The decoder ensures that `rights` do not exceed `granted_rights`, the MAC authenticates `granted_rights`.
+        fn verify_rights(&self, cap_id: CapID, required: Rights, rights: u8, granted_rights: u8, epoch: u32, mac: &CapMac) -> bool {
+            self.has_rights(cap_id, required, rights)
+                && self.verify_mac(cap_id, Rights::from_bits_truncate(granted_rights), epoch, mac)
+        }
+
+        /// Check that `source` holds the capability, if delegatee enforcement is enabled
+        async fn is_authorized_source(&self, cap: &Capability, source: &str) -> bool {
+            if !self.config.enforce_delegatees {
+                return true;
+            }
+            let authorized = cap.is_authorized(&IpAddress::from(source)).await;
+            if !authorized {
+                warn!("{:?} is neither owner nor delegatee of cap {:?}", source, cap.cap_id);
+            }
+            authorized
+        }
+
+        /// Next invocation sequence number for requests to `peer`
+        pub(crate) async fn next_invoke_sequence(&self, peer: &str) -> u64 {
+            let mut sequences = self.invoke_sequences.lock().await;
+            let sequence = sequences.entry(peer.to_string()).or_insert_with(initial_sequence);
+            *sequence += 1;
+            *sequence
+        }
+
+        /// Check an invocation for replays and clock skew.
+        /// Rejected invocations carry the response code to answer with, if any
+        async fn check_invoke_freshness(&self, source: &str, hdr: &RequestInvokeHeader) -> Result<(), Option<u64>> {
+            if let Some(max_skew) = self.config.max_invoke_skew_ms {
+                let timestamp = hdr.timestamp;
+                if now_ms().abs_diff(timestamp) > max_skew {
+                    warn!("rejecting RequestInvoke from {:?} with timestamp {:?} outside the allowed skew", source, timestamp);
+                    return Err(Some(RESPONSE_REJECTED));
+                }
+            }
+
+            if !self.config.replay_protection {
+                return Ok(());
+            }
+
+            let sequence = hdr.sequence;
+            match self.replay_windows.lock().await.entry(source.to_string()).or_default().check(sequence) {
+                ReplayCheck::Fresh => Ok(()),
+                ReplayCheck::Duplicate(response_code) => {
+                    debug!("RequestInvoke {:?} from {:?} already seen, not running it again", sequence, source);
+                    Err(response_code)
+                }
+                ReplayCheck::Stale => {
+                    warn!("rejecting stale or replayed RequestInvoke {:?} from {:?}", sequence, source);
+                    Err(None)
+                }
+            }
+        }
+
+        async fn send_request_response(&self, cap_id: CapID, dest: String, stream_id: u32, response_code: u64) {
+            let packet: Box<[u8; std::mem::size_of::<RequestResponseHeader>()]> =
+                RequestResponseHeader::construct(cap_id, stream_id, response_code)
+                    .await
+                    .into();
+            debug!("Sent Response packet with code {:?} to {:?}", response_code, dest);
+            let _ = self
+                .send(SendRequest::new(dest, packet), false)
+                .await;
+        }
+
+        /// Tell `source` and the control plane that `cap_id` is not valid for the request on `stream_id`
+        async fn send_cap_invalid(&self, cap_id: CapID, source: String, stream_id: u32) {
+            self.audit(AuditEvent::CapInvalid, cap_id, &source, AuditOutcome::Rejected);
+            self.publish(ServiceEvent::CapInvalid { cap_id, peer: source.clone() });
+            let packet: Box<[u8; std::mem::size_of::<CapInvalidHeader>()]> =
+                CapInvalidHeader::construct(cap_id, source.as_str().into(), stream_id)
+                    .into();
+
+            #[cfg(feature="directCPcommunication")]
+            self.send(SendRequest::new(self.config.switch_addr.clone(), packet.clone()), false)
+                .await;
+
+            self.send(SendRequest::new(source, packet), false)
+                .await;
+        }
+
+        /// Track a lease handed out to `delegatee` and revoke the delegation once it expires
+        pub(crate) async fn grant_lease(&self, cap_id: CapID, delegatee: String, expiry_ms: u64, duration_ms: u64) {
+            self.leases.lock().await.insert((cap_id, delegatee.clone()), Lease { expiry_ms, duration_ms });
+            let s = self.clone();
+            tokio::spawn(async move { s.watch_lease(cap_id, delegatee).await });
+        }
+
+        /// Sleep until the lease expires, renewals move the expiry and keep the watcher asleep
+        async fn watch_lease(&self, cap_id: CapID, delegatee: String) {
+            loop {
+                let lease_expiry_ms = match self.leases.lock().await.get(&(cap_id, delegatee.clone())) {
+                    Some(lease) => lease.expiry_ms,
+                    // revoked or reset in the meantime
+                    None => return,
+                };
+                let now = now_ms();
+                if now < lease_expiry_ms {
+                    tokio::time::sleep(Duration::from_millis(lease_expiry_ms - now)).await;
+                    continue;
+                }
+
+                let mut leases = self.leases.lock().await;
+                if leases.get(&(cap_id, delegatee.clone())).is_some_and(|lease| lease.expiry_ms <= now_ms()) {
+                    leases.remove(&(cap_id, delegatee.clone()));
+                    drop(leases);
+                    debug!("lease of cap {:?} for {:?} expired", cap_id, delegatee);
+                    if let Some(cap) = self.cap_table.get(cap_id).await {
+                        let cap = cap.lock().await.clone();
+                        let report = cap.revoke_delegatee(self, delegatee.as_str().into()).await;
+                        if !report.timed_out.is_empty() {
+                            warn!("revocation of expired lease not acknowledged by {:?}", report.timed_out);
+                        }
+                        self.reclaim_if_unheld(cap_id).await;
+                    }
+                    return;
+                }
+            }
+        }
+
+        /// Drop the local copy of a delegated capability and tell the owner it is no longer held here
+        pub async fn close_capability(&self, cap: Arc<Mutex<Capability>>) {
+            let cap = cap.lock().await;
+            self.cap_table.remove(cap.cap_id).await;
+            if cap.is_owned() {
+                return;
+            }
+            let packet: Box<[u8; std::mem::size_of::<CapCloseHeader>()]> = CapCloseHeader::construct(cap.cap_id).into();
+            let owner: String = cap.owner_address().into();
+            self.audit(AuditEvent::Close, cap.cap_id, &owner, AuditOutcome::Success);
+            let _ = self.send(SendRequest::new(owner, packet), false).await;
+        }
+
+        /// Register the callback run when an owned capability is reclaimed because it has no holders left,
+        /// see `Config::reclaim_unheld_objects`
+        pub async fn on_reclaim(&self, callback: Box<dyn Fn(CapID, CapType) + Send + Sync>) {
+            *self.reclaim_callback.lock().await = Some(callback);
+        }
+
+        /// Remove an owned capability and its objects if reclamation is enabled and no holder is left
+        async fn reclaim_if_unheld(&self, cap_id: CapID) {
+            if !self.config.reclaim_unheld_objects {
+                return;
+            }
+            let cap = match self.cap_table.get(cap_id).await {
+                Some(cap) => cap,
+                None => return,
+            };
+            let mut cap = cap.lock().await;
+            if !cap.is_owned() || !cap.holders().await.is_empty() {
+                return;
+            }
+            self.cap_table.remove(cap_id).await;
+            cap.release_objects();
+            debug!("reclaimed cap {:?} without holders", cap_id);
+            self.audit(AuditEvent::Reclaim, cap_id, self.config.advertised_address(), AuditOutcome::Success);
+            if let Some(callback) = self.reclaim_callback.lock().await.as_ref() {
+                callback(cap_id, cap.cap_type);
+            }
+        }
+
         pub async fn delete_capability(&self, cap: Arc<Mutex<Capability>>) {
             self.cap_table.remove(cap.lock().await.cap_id).await;
         }
 
+        /// Revoke all capabilities and let `run` shut the service down.
+        /// From now on only responses to outstanding streams are processed.
         pub async fn terminate(&self) {
             info!("Terminating Service");
+            self.shutting_down.store(true, Ordering::SeqCst);
+
+            // persisted caps stay valid across restarts, only the copies held by this service are revoked
+            let persisted = self.config.cap_snapshot.is_some();
+            if persisted {
+                if let Err(e) = self.snapshot().await {
+                    error!("failed to write cap snapshot: {:?}", e);
+                }
+            }
 
-            for cap_id in self.cap_table.get_capids().await {
-                let cap =  self.cap_table.get(cap_id).await;
-                if let Some(cap) = cap {
-                    cap.lock().await.revoke(self.clone()).await.unwrap();
+            // revocations wait for acknowledgements, revoke all caps in parallel
+            let mut revocations = Vec::new();
+            for (meta, cap) in self.cap_table.get_caps_with_meta().await {
+                if persisted && meta.owned {
+                    continue;
+                }
+                let cap = cap.lock().await.clone();
+                let s = self.clone();
+                revocations.push(tokio::spawn(async move { cap.revoke_delegations(s).await }));
+            }
+            for revocation in revocations {
+                match revocation.await {
+                    Ok(Ok(report)) => {
+                        if !report.timed_out.is_empty() {
+                            warn!("revocation not acknowledged by {:?}", report.timed_out);
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        error!("failed to revoke cap on shutdown: {:?}", e);
+                        self.failed_revocations.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Err(e) => {
+                        error!("revocation task failed on shutdown: {:?}", e);
+                        self.failed_revocations.fetch_add(1, Ordering::SeqCst);
+                    }
                 }
             }
+            if let Some(dropped) = self.audit_log.as_ref().map(|audit_log| audit_log.dropped()).filter(|dropped| *dropped > 0) {
+                warn!("dropped {:?} audit records, the audit log could not keep up", dropped);
+            }
             self.termination_notifier.clone().notify_waiters();
-            info!("refcount of socket should now be 1, is {:?}", Arc::strong_count(&self.socket));
+            info!("refcount of socket should now be 1, is {:?}", Arc::strong_count(&self.transport));
             
             #[cfg(feature="net-stats")]
-            info!("Send Counter: {:?}, Receive Counter: {:?}", self.send_counter.lock().await, self.recv_counter.lock().await, )
+            info!("Send Counter: {:?}, Receive Counter: {:?}, Rate Limited Counter: {:?}, Malformed Counter: {:?}", self.send_counter.lock().await, self.recv_counter.lock().await, self.rate_limited_counter.lock().await, self.malformed_counter.lock().await)
         }
 
-        pub async fn run(&self) -> io::Result<()> {
+        /// Run the service until `terminate` is called.
+        /// On shutdown the send queue is flushed and running handlers are awaited until
+        /// `config.shutdown_timeout_ms` passed, everything left over is reported.
+        pub async fn run(&self) -> io::Result<ShutdownReport> {
+            let snapshot_handle = self.config.cap_snapshot_interval_ms.map(|interval| {
+                let s = self.clone();
+                tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(Duration::from_millis(interval)).await;
+                        if let Err(e) = s.snapshot().await {
+                            error!("failed to write cap snapshot: {:?}", e);
+                        }
+                    }
+                })
+            });
+
             let s = self.clone();
             let sender_handle = tokio::spawn(async move {
                 debug!("started sender thread");
@@ -181,14 +870,15 @@ pub mod tcap {
                     debug!("receive next packet from send queue");
                     let packet = s.receiver.clone().lock().await.recv().await;
                     if let Some(packet) = packet {
-                        s.response_notifiers
-                            .lock()
-                            .await
-                            .insert(packet.stream_id, packet.response_notification.clone());
-
-                        match s.socket.send_to(&packet.data, packet.dest.clone()).await {
+                        let sent = s.transport.send_to(&packet.data, &packet.dest).await;
+                        s.pending_sends.fetch_sub(1, Ordering::SeqCst);
+                        match sent {
                             Ok(b) => debug!("sent stream id {:?}, size: {:?}", packet.stream_id, b),
-                            Err(_) => panic!("failed to send network packet to {:?}", packet.dest),
+                            Err(e) => {
+                                // the peer may be unreachable for now, the packet is dropped like a lost datagram
+                                error!("failed to send network packet to {:?}, dropping it: {:?}", packet.dest, e);
+                                continue;
+                            }
                         };
                         #[cfg(feature="net-stats")]
                         s.send_counter.lock().await.add_assign(1);
@@ -203,16 +893,32 @@ pub mod tcap {
             let receiver_handle = tokio::spawn(async move {
                 debug!("Start receiver Thread");
                 loop {
-                    let mut buf = Vec::with_capacity(10000);
+                    let mut buf = Vec::with_capacity(RECV_BUFFER_SIZE);
 
-                    match s.socket.recv_buf_from(&mut buf).await {
+                    match s.transport.recv_buf_from(&mut buf).await {
                         Ok((received_bytes, sender)) => {
                             #[cfg(feature="net-stats")]
                             s.clone().recv_counter.lock().await.add_assign(1);
 
+                            let common = match CommonHeader::try_from(&buf[..]) {
+                                Ok(common) => common,
+                                Err(e) => {
+                                    s.drop_malformed(&sender.to_string(), e).await;
+                                    continue;
+                                }
+                            };
+
+                            // drop floods before spawning a handler for them
+                            if s.is_rate_limited(&common, sender).await {
+                                #[cfg(feature="net-stats")]
+                                s.rate_limited_counter.lock().await.add_assign(1);
+                                continue;
+                            }
+
                             let ss = s.clone();
+                            let handler = HandlerGuard::new(s.running_handlers.clone());
                             tokio::spawn(async move {
-                            let common = CommonHeader::from(buf[0..std::mem::size_of::<CommonHeader>()].to_vec());
+                            let _handler = handler;
                             let cmd = common.cmd;
                             debug!(
                                 "Service at {:?} Received packet from {:?} size {:?}, cmdtype {:?}",
@@ -223,36 +929,48 @@ pub mod tcap {
                                 return;
                             }
 
-                            assert!(
-                                received_bytes >= std::mem::size_of::<CommonHeader>(),
-                                "Received packets must include the common header"
-                            );
                             let stream_id = common.stream_id;
                             debug!("Received packet with stream id {:?}", stream_id);
 
-                            match ss.response_notifiers.lock().await.get(&stream_id) {
+                            // acks share the stream id of the transfer we serve, they are never responses
+                            if CmdType::from(common.cmd) == CmdType::MemoryCopyAck {
+                                ss.parse(sender.to_string(), buf, common).await;
+                                return;
+                            }
+
+                            // only the peer a stream was opened with can answer on it
+                            let notifier = ss.response_notifiers.lock().await.get(&(sender.to_string(), stream_id)).cloned();
+                            match notifier {
                                 Some(notifier) => {
                                     if CmdType::from(common.cmd) == CmdType::MemoryCopyResponse{
-                                        let hdr = MemoryCopyResponseHeader::from(buf.clone());
+                                        let hdr = match MemoryCopyResponse::try_from(&buf[..]) {
+                                            Ok(resp) => resp.header,
+                                            Err(e) => return ss.drop_malformed(&sender.to_string(), e).await,
+                                        };
+                                        ss.ack_memory_copy(sender.to_string(), &hdr).await;
                                         ss.responses.lock().await.insert(
-                                            stream_id + hdr.sequence,
+                                            (sender.to_string(), stream_id, hdr.sequence),
                                             Response {
                                                 sender: sender.to_string(),
                                                 data: buf,
                                             },
                                         );
                                     } else {
-                                    ss.responses.lock().await.insert(
-                                        stream_id,
-                                        Response {
-                                            sender: sender.to_string(),
-                                            data: buf,
-                                        },
-                                    );
-                                }
+                                        ss.responses.lock().await.insert(
+                                            (sender.to_string(), stream_id, 0),
+                                            Response {
+                                                sender: sender.to_string(),
+                                                data: buf,
+                                            },
+                                        );
+                                    }
                                     notifier.add_permits(1);
                                     debug!("notified stream id {:?}", stream_id);
                                 }
+                                None if ss.shutting_down.load(Ordering::SeqCst) => {
+                                    debug!("shutting down, rejecting unsolicited packet on stream {:?}", stream_id);
+                                    ss.rejected_packets.fetch_add(1, Ordering::SeqCst);
+                                }
                                 None => {
                                     debug!("stream {:?} is not waited for. Trying to parse unsolicited packet", stream_id);
 
@@ -269,76 +987,256 @@ pub mod tcap {
             });
             
             self.termination_notifier.clone().notified().await;
-            
-            let  _ = sender_handle.abort();
-            let  _ = receiver_handle.abort();
+
+            // responses to outstanding streams are still received while draining
+            let deadline = Instant::now() + Duration::from_millis(self.config.shutdown_timeout_ms);
+            while Instant::now() < deadline
+                && (self.running_handlers.load(Ordering::SeqCst) > 0 || self.pending_sends.load(Ordering::SeqCst) > 0) {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+
+            receiver_handle.abort();
+            sender_handle.abort();
+            if let Some(snapshot_handle) = snapshot_handle {
+                snapshot_handle.abort();
+            }
+
+            let report = ShutdownReport {
+                dropped_packets: self.pending_sends.load(Ordering::SeqCst),
+                rejected_packets: self.rejected_packets.load(Ordering::SeqCst),
+                unfinished_handlers: self.running_handlers.load(Ordering::SeqCst),
+                failed_revocations: self.failed_revocations.load(Ordering::SeqCst),
+            };
+            if report != ShutdownReport::default() {
+                warn!("Service shut down with unfinished work: {:?}", report);
+            }
 
             info!("aborted all service threads");
-            Ok(())
+            Ok(report)
+        }
+
+        /// Allocate a stream id that is not used by any open stream with `peer` and register its notifier.
+        /// Only packets sent by `peer` are routed to the stream, other peers using the id are parsed as unsolicited packets.
+        /// Stream id 0 is never allocated.
+        pub(crate) async fn open_stream(&self, peer: &str) -> u32 {
+            let peer = peer_key(peer);
+            let mut notifiers = self.response_notifiers.lock().await;
+            loop {
+                let stream_id = rand::Rng::gen::<u32>(&mut rand::thread_rng());
+                if stream_id != 0 && !notifiers.contains_key(&(peer.clone(), stream_id)) {
+                    notifiers.insert((peer, stream_id), Arc::new(Semaphore::new(0)));
+                    return stream_id;
+                }
+            }
+        }
+
+        /// Release a stream opened with `open_stream`, responses that were not collected are dropped
+        pub(crate) async fn close_stream(&self, peer: &str, stream_id: u32) {
+            let peer = peer_key(peer);
+            self.response_notifiers.lock().await.remove(&(peer.clone(), stream_id));
+            self.responses.lock().await.retain(|(p, s, _), _| *s != stream_id || *p != peer);
         }
 
         pub(crate) async fn send(&self, r: SendRequest, wait_for_response: bool) -> Option<Arc<Semaphore>> {
-            let notification = r.response_notification.clone();
+            // register before sending, the response may arrive before the sender thread returns
+            let notification = match wait_for_response {
+                true => Some(self.response_notifiers
+                    .lock()
+                    .await
+                    .entry((peer_key(&r.dest), r.stream_id))
+                    .or_insert_with(|| Arc::new(Semaphore::new(0)))
+                    .clone()),
+                false => None,
+            };
             debug!(
                 "sending Request: {:?} via mpsc",
                 r.stream_id,
             );
-            let _ = self.send_channel.clone().lock().await.send(r).await;
-
-            if wait_for_response {
-                return Some(notification.clone());
+            self.pending_sends.fetch_add(1, Ordering::SeqCst);
+            if self.send_channel.clone().lock().await.send(r).await.is_err() {
+                self.pending_sends.fetch_sub(1, Ordering::SeqCst);
             }
-            None
+
+            notification
+        }
+
+
+        /// Acknowledge a received memory copy chunk, so the sender can advance its window
+        pub(crate) async fn ack_memory_copy(&self, dest: String, hdr: &MemoryCopyResponseHeader) {
+            let packet: Box<[u8; std::mem::size_of::<MemoryCopyAckHeader>()]> =
+                MemoryCopyAckHeader::construct(hdr.common.cap_id, hdr.common.stream_id, hdr.sequence).into();
+            self.send(SendRequest::new(dest, packet), false).await;
         }
 
+        /// Send the chunks of a memory object with at most `config.memcopy_window` chunks in flight.
+        /// Chunks not acknowledged within `MEMCOPY_ACK_TIMEOUT_MS` are sent again.
+        async fn send_memory_copy(&self, dest: String, stream_id: u32, packets: Vec<MemoryCopyResponse>) {
+            let (ack_sender, mut acks) = mpsc::unbounded_channel::<u32>();
+            self.copy_windows.lock().await.insert((dest.clone(), stream_id), ack_sender);
+
+            let window = self.config.memcopy_window.max(1);
+            let mut in_flight: HashSet<u32> = HashSet::new();
+            let mut next = 0;
+            let mut retransmits = 0;
+            loop {
+                while in_flight.len() < window && next < packets.len() {
+                    let sequence = packets[next].header.sequence;
+                    self.send_memory_copy_chunk(dest.clone(), packets[next].clone()).await;
+                    in_flight.insert(sequence);
+                    next += 1;
+                }
+
+                if in_flight.is_empty() {
+                    debug!("memory copy on stream {:?} completed", stream_id);
+                    break;
+                }
+
+                match tokio::time::timeout(Duration::from_millis(MEMCOPY_ACK_TIMEOUT_MS), acks.recv()).await {
+                    Ok(Some(sequence)) => {
+                        if in_flight.remove(&sequence) {
+                            retransmits = 0;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        retransmits += 1;
+                        if retransmits > MEMCOPY_MAX_RETRANSMITS {
+                            warn!("giving up memory copy on stream {:?} to {:?}, {:?} chunks unacknowledged", stream_id, dest, in_flight.len());
+                            break;
+                        }
+                        debug!("resending {:?} unacknowledged chunks of stream {:?}", in_flight.len(), stream_id);
+                        for packet in packets[..next].iter() {
+                            let sequence = packet.header.sequence;
+                            if in_flight.contains(&sequence) {
+                                self.send_memory_copy_chunk(dest.clone(), packet.clone()).await;
+                            }
+                        }
+                    }
+                }
+            }
+
+            self.copy_windows.lock().await.remove(&(dest, stream_id));
+        }
 
-        pub(crate) async fn get_response(&self, stream_id: u32) -> Option<Response> {
-            self.responses.lock().await.remove(&stream_id)
+        async fn send_memory_copy_chunk(&self, dest: String, packet: MemoryCopyResponse) {
+            let resp: Box<[u8]> = packet.into();
+            debug!("Sent Response packet to {:?}", dest);
+            let _ = self
+                .send(SendRequest::new(dest, resp), false)
+                .await;
         }
 
-        pub(crate) async fn get_response_no_delete(&self, stream_id: u32) -> Option<Response> {
-            self.responses.lock().await.get(&stream_id).cloned()
+        pub(crate) async fn get_response(&self, peer: &str, stream_id: u32, sequence: u32) -> Option<Response> {
+            self.responses.lock().await.remove(&(peer_key(peer), stream_id, sequence))
         }
 
         async fn parse(&self, source: String, packet: Vec<u8>, common: CommonHeader) {
-            assert!(
-                packet.len() >= std::mem::size_of::<CommonHeader>(),
-                "Received packets must include the common header"
-            );
             let command = common.cmd;
             match CmdType::from(command) {
-                CmdType::Nop => todo!(),
-                CmdType::CapGetInfo => todo!(),
-                CmdType::CapIsSame => todo!(),
-                CmdType::CapDiminish => todo!(),
-                CmdType::CapClose => todo!(),
+                CmdType::CapClose => {
+                    let hdr = match CapCloseHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    let cap_id = hdr.common.cap_id;
+                    let cap = match self.cap_table.get_with_meta(cap_id).await {
+                        Some((meta, cap)) if meta.owned => cap,
+                        _ => {
+                            debug!("ignoring close of cap {:?} not owned by this service", cap_id);
+                            return;
+                        }
+                    };
+                    if !cap.lock().await.remove_holder(&source.as_str().into()).await {
+                        self.audit(AuditEvent::Close, cap_id, &source, AuditOutcome::Rejected);
+                        return;
+                    }
+                    self.leases.lock().await.remove(&(cap_id, source.clone()));
+                    self.audit(AuditEvent::Close, cap_id, &source, AuditOutcome::Success);
+                    self.reclaim_if_unheld(cap_id).await;
+                    self.publish(ServiceEvent::CapClosed { cap_id, holder: source });
+                }
                 CmdType::CapInvalid => {
+                    self.audit(AuditEvent::CapInvalid, common.cap_id, &source, AuditOutcome::Rejected);
                     error!("Received CapInvalid packet, but not as response to outgoing stream");
                 }
                 CmdType::CapRevoke => {
-                    let hdr = RevokeCapHeader::from(packet);
+                    let hdr = match RevokeCapHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
                     debug!("Received CapRevoke: {:?}", hdr);
-                    self.cap_table.get(hdr.cap_id).await.unwrap().lock().await.revoke(self.clone()).await.unwrap();
+                    let cap = match self.cap_table.get(hdr.cap_id).await {
+                        Some(cap) => Some(cap.lock().await.clone()),
+                        None => None,
+                    };
+                    if let Some(cap) = cap.as_ref() {
+                        if !cap.may_revoke(&IpAddress::from(source.as_str())) {
+                            warn!("dropping revocation of cap {:?} from {:?}, neither owner nor delegator", { hdr.cap_id }, source);
+                            self.audit(AuditEvent::Revoke, hdr.cap_id, &source, AuditOutcome::Rejected);
+                            return;
+                        }
+                    }
+
+                    // acknowledge before cascading, so the revoker does not wait for the whole subtree
+                    let ack: Box<[u8; std::mem::size_of::<RevokeAckHeader>()]> =
+                        RevokeAckHeader::construct(hdr.cap_id, hdr.common.stream_id).into();
+                    let _ = self.send(SendRequest::new(source.clone(), ack), false).await;
+
+                    // the owner revokes all copies in the derivation tree and resends unacknowledged revocations,
+                    // so copies may receive the revocation more than once
+                    let cap = match cap {
+                        Some(cap) => cap,
+                        None => {
+                            debug!("cap {:?} already revoked", { hdr.cap_id });
+                            return;
+                        }
+                    };
+                    if let Err(e) = cap.revoke_delegations(self.clone()).await {
+                        error!("failed to revoke cap {:?} on behalf of {:?}: {:?}", { hdr.cap_id }, source, e);
+                        self.audit(AuditEvent::Revoke, hdr.cap_id, &source, AuditOutcome::Failed);
+                        return;
+                    }
+                    self.audit(AuditEvent::Revoke, hdr.cap_id, &source, AuditOutcome::Success);
+                    self.publish(ServiceEvent::CapRevoked { cap_id: hdr.cap_id, revoker: source });
                 }
-                CmdType::RequestCreate => todo!(),
                 CmdType::RequestInvoke => {
-                    let hdr = RequestInvokeHeader::from(packet);
+                    let hdr = match RequestInvokeHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
                     debug!("Received RequestInvoke: {:?}", hdr);
 
-                    if !self.cap_table.contains(hdr.common.cap_id).await {
-                        let packet: Box<[u8; std::mem::size_of::<CapInvalidHeader>()]> =
-                            CapInvalidHeader::construct(hdr.common.cap_id, source.clone().as_str().into(), hdr.common.stream_id)
-                                .into();
-                        #[cfg(feature="directCPcommunication")]
-                        self.send(SendRequest::new(self.config.switch_addr.clone(), packet.clone()), false)
-                            .await;
-                        
-                        self.send(SendRequest::new(source, packet), false)
-                            .await;
+                    // only authenticated requests learn that their cap is stale, the MAC covers the epoch
+                    let (meta, cap) = match self.cap_table.get_view(hdr.common.cap_id).await {
+                        Some(entry) if self.has_rights(hdr.common.cap_id, Rights::INVOKE, hdr.rights) && self.verify_invocation_mac(&hdr) => entry,
+                        _ => {
+                            self.audit(AuditEvent::Invoke, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                            self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                            return;
+                        }
+                    };
+                    if !self.verify_epoch(hdr.common.cap_id, hdr.epoch, &source, hdr.common.stream_id).await {
+                        self.audit(AuditEvent::Invoke, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        return;
+                    }
+                    if !self.is_authorized_source(&cap, &source).await {
+                        self.audit(AuditEvent::Invoke, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                        return;
+                    }
+
+                    // flag bits were validated when decoding
+                    let require_response = Flags::from_bits_truncate(hdr.flags)
+                        .contains(Flags::REQUIRE_RESPONSE);
+
+                    if let Err(response_code) = self.check_invoke_freshness(&source, &hdr).await {
+                        self.audit(AuditEvent::Invoke, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        if let (Some(response_code), true) = (response_code, require_response) {
+                            self.send_request_response(hdr.common.cap_id, source, hdr.common.stream_id, response_code).await;
+                        }
                         return;
                     }
 
-                    let cap = self.cap_table.get(hdr.common.cap_id).await.unwrap();
                     let mut continuations = vec!();
                     for i in 0..hdr.number_of_conts.min(4) {
                         let c = match hdr.continutaion_cap_ids[i as usize] {
@@ -354,103 +1252,264 @@ pub mod tcap {
                         };
                         continuations.push(c);
                     }
-                    let capid = cap.lock().await.cap_id;
+                    let capid = meta.cap_id;
+
+                    // the view runs the bound object without locking the cap
+                    let result = cap.run(continuations).await;
+                    let (response_code, outcome) = match result {
+                        Ok(_) => (RESPONSE_OK, AuditOutcome::Success),
+                        Err(_) => (RESPONSE_FAILED, AuditOutcome::Failed),
+                    };
+                    self.audit(AuditEvent::Invoke, capid, &source, outcome);
+                    self.publish(ServiceEvent::Invoked { cap_id: capid, caller: source.clone(), success: result.is_ok() });
+                    if self.config.replay_protection {
+                        if let Some(window) = self.replay_windows.lock().await.get_mut(&source) {
+                            window.record_response(hdr.sequence, response_code);
+                        }
+                    }
 
-                    let result = cap
-                    .lock()
-                    .await
-                    .run(continuations)
-                    .await;
                     debug!("Flags: {:?}", hdr.flags);
-                    if ! Flags::from_bits(hdr.flags)
-                            .expect("Invalid Bits set in RequestInvoke Flag")
-                            .contains(Flags::REQUIRE_RESPONSE) {
+                    if !require_response {
                         debug!("Not sending response packet");
                         return;
                     }
 
-                    let packet: Box<[u8; std::mem::size_of::<RequestResponseHeader>()]> = match result
-                    {
-                        Ok(_) => {
-                            debug!("result ok: constructing reponse header with code 0");
-                            RequestResponseHeader::construct(capid, hdr.common.stream_id, 0)
-                                .await
-                                .into()
-                        }
-                        Err(_) => {
-                            debug!("result ok: constructing reponse header with code 100");
-                            RequestResponseHeader::construct(capid, hdr.common.stream_id, 100)
-                                .await
-                                .into()
-                        }
-                    };
-                    debug!("Sent Response packet to {:?}", source);
-                    let _ = self
-                        .send(SendRequest::new(source, packet), false)
-                        .await;
+                    self.send_request_response(capid, source, hdr.common.stream_id, response_code).await;
                 }
-                CmdType::RequestReceive => todo!(),
-                CmdType::None => todo!(),
                 CmdType::InsertCap => {
                     debug!("received insert cap packet with len {:?}", packet.len());
 
-                    let hdr = InsertCapHeader::from(packet);
+                    let hdr = match InsertCapHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
                     debug!("Received CapInsert: {:?}", hdr);
+                    if !is_valid_delegation_depth(hdr.delegator_depth, hdr.delegation_depth) {
+                        warn!("rejecting delegation of cap {:?} from {:?} with depth {:?}, delegator depth {:?}",
+                            { hdr.common.cap_id }, source, { hdr.delegation_depth }, { hdr.delegator_depth });
+                        self.audit(AuditEvent::InsertCap, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        self.send_request_response(hdr.common.cap_id, source, hdr.common.stream_id, RESPONSE_REJECTED).await;
+                        return;
+                    }
                     let cap = Arc::new(Mutex::new(Capability::from(hdr)));
                     cap.lock().await.service = Some(Arc::new(self.clone()));
-                    let _ = self    
-                        .cap_table
-                        .insert(cap)
-                        .await;
+                    // a repeated delegation replaces the held copy and needs no room
+                    if !self.insert_received(cap).await {
+                        warn!("rejecting delegation of cap {:?} from {:?}, {:?} received caps held",
+                            { hdr.common.cap_id }, source, self.cap_table.received_count());
+                        self.audit(AuditEvent::InsertCap, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        let capacity = self.config.max_received_caps.unwrap_or_default() as u64;
+                        let packet: Box<[u8; std::mem::size_of::<CapTableFullHeader>()]> =
+                            CapTableFullHeader::construct(hdr.common.cap_id, hdr.common.stream_id, capacity).into();
+                        let _ = self.send(SendRequest::new(source, packet), false).await;
+                        return;
+                    }
+                    self.audit(AuditEvent::InsertCap, hdr.common.cap_id, &source, AuditOutcome::Success);
+                    self.send_request_response(hdr.common.cap_id, source.clone(), hdr.common.stream_id, RESPONSE_OK).await;
+                    self.publish(ServiceEvent::CapInserted { cap_id: hdr.common.cap_id, delegator: source });
+                }
+                CmdType::LeaseRenew => {
+                    let hdr = match LeaseRenewHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    debug!("Received LeaseRenew: {:?}", hdr);
+                    let lease_expiry_ms = {
+                        let mut leases = self.leases.lock().await;
+                        let now = now_ms();
+                        match leases.get_mut(&(hdr.common.cap_id, source.clone())) {
+                            Some(lease) if lease.expiry_ms > now => {
+                                lease.expiry_ms = lease.renewed_expiry(now, hdr.duration_ms);
+                                Some(lease.expiry_ms)
+                            }
+                            _ => None,
+                        }
+                    };
+                    match lease_expiry_ms {
+                        Some(lease_expiry_ms) => {
+                            let packet: Box<[u8; std::mem::size_of::<LeaseRenewResponseHeader>()]> =
+                                LeaseRenewResponseHeader::construct(hdr.common.cap_id, hdr.common.stream_id, lease_expiry_ms).into();
+                            let _ = self.send(SendRequest::new(source, packet), false).await;
+                        }
+                        None => {
+                            warn!("{:?} has no active lease of cap {:?} to renew", source, { hdr.common.cap_id });
+                            self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                        }
+                    }
+                }
+                CmdType::DelegationNotice => {
+                    let hdr = match DelegationNoticeHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    debug!("Received DelegationNotice: {:?}", hdr);
+                    let cap_id = hdr.common.cap_id;
+                    let delegatee = IpAddress { address: hdr.delegatee_ip, netmask: [0, 0, 0, 0], port: hdr.delegatee_port };
+                    let cap = match self.cap_table.get_with_meta(cap_id).await {
+                        Some((meta, cap)) if meta.owned => cap.lock().await.clone(),
+                        _ => {
+                            debug!("ignoring delegation notice from {:?} for cap {:?} not owned by this service", source, cap_id);
+                            return;
+                        }
+                    };
+                    // the delegator's MAC authenticates the rights it was granted, the delegatee gets at most those
+                    let granted_rights = Rights::from_bits_truncate(hdr.granted_rights);
+                    if !granted_rights.contains(Rights::DELEGATE) || !self.verify_mac(cap_id, granted_rights, hdr.epoch, &hdr.mac) {
+                        warn!("rejecting delegation of cap {:?} by {:?} without the delegate right", cap_id, source);
+                        self.audit(AuditEvent::Delegate, cap_id, &source, AuditOutcome::Rejected);
+                        self.send_request_response(cap_id, source, hdr.common.stream_id, RESPONSE_REJECTED).await;
+                        return;
+                    }
+                    if !self.verify_epoch(cap_id, hdr.epoch, &source, hdr.common.stream_id).await {
+                        self.audit(AuditEvent::Delegate, cap_id, &source, AuditOutcome::Rejected);
+                        return;
+                    }
+                    let derivation = Derivation { delegator: source.as_str().into(), delegatee, depth: hdr.delegation_depth };
+                    let delegator_depth = match cap.record_derivation(derivation.delegator, delegatee, hdr.delegation_depth).await {
+                        Some(delegator_depth) => delegator_depth,
+                        None => {
+                            warn!("rejecting delegation of cap {:?} by {:?} with depth {:?}, it does not hold the cap or may not delegate that deep",
+                                cap_id, source, { hdr.delegation_depth });
+                            self.audit(AuditEvent::Delegate, cap_id, &source, AuditOutcome::Rejected);
+                            self.send_request_response(cap_id, source, hdr.common.stream_id, RESPONSE_REJECTED).await;
+                            return;
+                        }
+                    };
+
+                    // sub-delegations of a leased cap end with the delegator's lease
+                    let lease_expiry_ms = self.leases.lock().await.get(&(cap_id, source.clone())).map(|lease| lease.expiry_ms).unwrap_or(0);
+                    let rights = Rights::from_bits_truncate(hdr.rights);
+                    let response_code = match cap.insert_at(self, derivation, rights, delegator_depth, lease_expiry_ms).await {
+                        Ok(()) => {
+                            self.audit(AuditEvent::Delegate, cap_id, &String::from(delegatee), AuditOutcome::Success);
+                            RESPONSE_OK
+                        }
+                        Err(e) => {
+                            warn!("delegation of cap {:?} by {:?} failed: {:?}", cap_id, source, e);
+                            match e.kind() {
+                                io::ErrorKind::Other => RESPONSE_CAP_TABLE_FULL,
+                                _ => RESPONSE_REJECTED,
+                            }
+                        }
+                    };
+                    self.send_request_response(cap_id, source, hdr.common.stream_id, response_code).await;
+                }
+                CmdType::CapRevokeAck => {
+                    debug!("dropping revocation ack from {:?} on closed stream {:?}", source, { common.stream_id });
+                }
+                CmdType::CapTableFull => {
+                    let hdr = match CapTableFullHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    let cap_id = hdr.common.cap_id;
+                    // answers on open streams are handled by `Capability::insert_at`, this one came after it gave up waiting
+                    warn!("{:?} rejected the delegation of cap {:?}, it holds its maximum of {:?} received caps", source, cap_id, { hdr.capacity });
+                    let cap = match self.cap_table.get(cap_id).await {
+                        Some(cap) => cap.lock().await.clone(),
+                        None => return,
+                    };
+                    self.drop_rejected_delegation(&cap, &IpAddress::from(source.as_str())).await;
+                }
+                CmdType::CapStaleEpoch => {
+                    debug!("dropping stale epoch answer from {:?} on closed stream {:?}", source, { common.stream_id });
+                }
+                CmdType::LeaseRenewResponse => {
+                    debug!("dropping lease renewal response from {:?} on closed stream {:?}", source, { common.stream_id });
                 }
                 CmdType::RequestResponse => {
                     debug!("Received Request Response");
-                    let hdr = RequestResponseHeader::from(packet.clone());
+                    let hdr = match RequestResponseHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
                     let streamid = hdr.common.stream_id;
-                    self.responses.lock().await.insert(streamid, Response { sender: source, data: packet });
-                    self.response_notifiers.lock().await.get(&streamid).unwrap().add_permits(1);
+                    debug!("dropping response from {:?} on closed stream {:?}", source, streamid);
                 },
                 CmdType::MemoryCopy => {
                     debug!("Received MemoryCopy");
-                    let hdr = MemoryCopyRequestHeader::from(packet.clone());
-                    if !self.cap_table.contains(hdr.common.cap_id).await {
-                        let packet: Box<[u8; std::mem::size_of::<CapInvalidHeader>()]> =
-                            CapInvalidHeader::construct(hdr.common.cap_id, source.clone().as_str().into(), hdr.common.stream_id)
-                                .into();
-
-                        #[cfg(feature="directCPcommunication")]
-                        self.send(SendRequest::new(self.config.switch_addr.clone(), packet.clone()), false)
-                            .await;
-
-                        self.send(SendRequest::new(source, packet), false)
-                            .await;
+                    let hdr = match MemoryCopyRequestHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    // only authenticated requests learn that their cap is stale, the MAC covers the epoch
+                    let (meta, cap) = match self.cap_table.get_view(hdr.common.cap_id).await {
+                        Some(entry) if self.verify_rights(hdr.common.cap_id, Rights::READ, hdr.rights, hdr.granted_rights, hdr.epoch, &hdr.mac) => entry,
+                        _ => {
+                            self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                            self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                            return;
+                        }
+                    };
+                    if !self.verify_epoch(hdr.common.cap_id, hdr.epoch, &source, hdr.common.stream_id).await {
+                        self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        return;
+                    }
+                    if !self.is_authorized_source(&cap, &source).await {
+                        self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
                         return;
                     }
 
-                    let cap = self.cap_table.get(hdr.common.cap_id).await.unwrap();
-
-                    if cap.lock().await.cap_type != CapType::Memory {
-                        panic!("someone ties to capy memory from a non-memory type capability");
+                    if meta.cap_type != CapType::Memory {
+                        warn!("{:?} tried to copy memory from non-memory cap {:?}", source, { hdr.common.cap_id });
+                        self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                        self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                        return;
                     }
 
-                    let packets = MemoryCopyResponseHeader::construct(cap.lock().await.get_buffer().await, hdr.common.cap_id, hdr.common.stream_id).await;
-                    for packet in packets {
-                        let resp: Box<[u8; std::mem::size_of::<MemoryCopyResponseHeader>()]> = packet.into();
+                    // both sides have to agree on the chunk size, use the smaller of the requested and the local one
+                    let chunk_size = match hdr.chunk_size {
+                        0 => self.config.chunk_size_for(&source),
+                        requested => (requested as usize).min(self.config.chunk_size_for(&source)),
+                    };
+                    // owned memory caps are local, only copies held for other owners are fetched first
+                    let buffer = match cap.local_buffer().await {
+                        Some(buffer) => Ok(buffer),
+                        None => match self.cap_table.get(meta.cap_id).await {
+                            Some(cap) => cap.lock().await.get_buffer().await,
+                            None => Err(InvokeError::Invalid),
+                        },
+                    };
+                    let buffer = match buffer {
+                        Ok(buffer) => buffer,
+                        Err(e) => {
+                            warn!("cannot serve MemoryCopy of cap {:?}: {:?}", { hdr.common.cap_id }, e);
+                            self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Rejected);
+                            self.send_cap_invalid(hdr.common.cap_id, source, hdr.common.stream_id).await;
+                            return;
+                        }
+                    };
+                    debug!("serving MemoryCopy to {:?} with chunk size {:?}", source, chunk_size);
+                    self.audit(AuditEvent::MemoryCopy, hdr.common.cap_id, &source, AuditOutcome::Success);
 
-                        debug!("Sent Response packet to {:?}", source.clone());
-                        let _ = self
-                            .send(SendRequest::new(source.clone(), resp), false)
-                            .await;
-                    }
+                    let packets = MemoryCopyResponse::construct(buffer, hdr.common.cap_id, hdr.common.stream_id, chunk_size).await;
+                    self.send_memory_copy(source, hdr.common.stream_id, packets).await;
+                },
+                CmdType::MemoryCopyAck => {
+                    let hdr = match MemoryCopyAckHeader::try_from(&packet[..]) {
+                        Ok(hdr) => hdr,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
+                    let streamid = hdr.common.stream_id;
+                    match self.copy_windows.lock().await.get(&(source, streamid)) {
+                        Some(window) => {
+                            let _ = window.send(hdr.sequence);
+                        }
+                        None => debug!("Received MemoryCopyAck for finished stream {:?}", streamid),
+                    };
                 },
                 CmdType::MemoryCopyResponse => {
                     debug!("Received MemoryCopyResponse");
-                    let hdr = MemoryCopyResponseHeader::from(packet.clone());
+                    let hdr = match MemoryCopyResponse::try_from(&packet[..]) {
+                        Ok(resp) => resp.header,
+                        Err(e) => return self.drop_malformed(&source, e).await,
+                    };
                     let streamid = hdr.common.stream_id;
 
-                    // TODO (@jkrbs): fix sequence and stream id mangling. This is an ungly hack
-                    self.responses.lock().await.insert(streamid+hdr.sequence, Response { sender: source.clone(), data: packet });
-                    self.response_notifiers.lock().await.get(&streamid).unwrap().add_permits(1);
+                    // still acknowledge, so the sender stops retransmitting to a closed stream
+                    self.ack_memory_copy(source.clone(), &hdr).await;
+                    debug!("dropping memory copy chunk {:?} from {:?} on closed stream {:?}", { hdr.sequence }, source, streamid);
                 },
                 _ => {
                     warn!("Unrecognized CMDType received");
@@ -486,4 +1545,194 @@ pub mod tcap {
             self.send(req, false).await;
         }
     }
+
+    mod tests {
+        #![allow(unused_imports, dead_code)]
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use clap::Parser;
+        use tokio::net::UdpSocket;
+        use tokio::sync::Mutex;
+
+        use super::{Lease, Service};
+        use crate::capabilities::tcap::InvokeError;
+        use crate::config::Config;
+        use crate::events::tcap::events::ServiceEvent;
+        use crate::packet_types::tcap::{CapInvalidHeader, Flags, InsertCapHeader, IpAddress, MemoryCopyAckHeader, MemoryCopyRequestHeader, MemoryCopyResponse, RequestInvokeHeader, RequestResponseHeader, RevokeCapHeader, RESPONSE_OK};
+        use crate::object::tcap::object::{MemoryObject, RequestObject};
+
+        /// Start a service on a free loopback port
+        async fn start(args: &[&str]) -> Service {
+            let mut argv = vec!["tcap", "-i", "lo", "-a", "127.0.0.1:0", "-s", "127.0.0.1:1"];
+            argv.extend_from_slice(args);
+            let service = Service::new(Config::parse_from(argv)).await;
+            let runner = service.clone();
+            tokio::spawn(async move { runner.run().await });
+            service
+        }
+
+        #[test]
+        fn test_lease_renewal_is_bounded() {
+            let lease = Lease { expiry_ms: 1_500, duration_ms: 1_000 };
+            assert_eq!(lease.renewed_expiry(1_000, 500), 1_500);
+            assert_eq!(lease.renewed_expiry(1_000, 60_000), 2_000, "renewals must not exceed the granted duration");
+            assert_eq!(lease.renewed_expiry(u64::MAX - 10, u64::MAX), u64::MAX, "renewals must not overflow");
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_unacknowledged_chunks_are_resent() {
+            let owner = start(&[]).await;
+            let cap = owner.create_capability().await;
+            cap.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(vec![1, 2, 3]).await))).await;
+            let cap = cap.lock().await.clone();
+
+            let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let request: Box<[u8; std::mem::size_of::<MemoryCopyRequestHeader>()]> =
+                MemoryCopyRequestHeader::construct(cap.cap_id, 1, 1, cap.rights(), cap.granted_rights(), cap.epoch(), cap.mac()).into();
+            receiver.send_to(&request[..], owner.address()).await.unwrap();
+
+            let mut buf = [0u8; 1024];
+            let mut sequences = Vec::new();
+            for _ in 0..3 {
+                let len = receiver.recv(&mut buf).await.unwrap();
+                sequences.push(MemoryCopyResponse::try_from(&buf[..len]).unwrap().header.sequence);
+            }
+            assert_eq!(sequences, vec![1, 2, 3]);
+
+            // the ack of the second chunk is lost
+            for sequence in [1, 3] {
+                let ack: Box<[u8; std::mem::size_of::<MemoryCopyAckHeader>()]> = MemoryCopyAckHeader::construct(cap.cap_id, 1, sequence).into();
+                receiver.send_to(&ack[..], owner.address()).await.unwrap();
+            }
+            let len = tokio::time::timeout(Duration::from_secs(2), receiver.recv(&mut buf)).await.unwrap().unwrap();
+            let resent = MemoryCopyResponse::try_from(&buf[..len]).unwrap();
+            assert_eq!({ resent.header.sequence }, 2, "only the unacknowledged chunk is resent");
+            assert_eq!(resent.buffer, vec![2]);
+
+            let ack: Box<[u8; std::mem::size_of::<MemoryCopyAckHeader>()]> = MemoryCopyAckHeader::construct(cap.cap_id, 1, 2).into();
+            receiver.send_to(&ack[..], owner.address()).await.unwrap();
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_delegations_carry_the_advertised_address() {
+            let config = Config::parse_from(["tcap", "-i", "lo", "-a", "0.0.0.0:0", "--advertise-address", "127.0.0.1:0", "-s", "127.0.0.1:1"]);
+            let owner = Service::new(config).await;
+            let runner = owner.clone();
+            tokio::spawn(async move { runner.run().await });
+            let advertised = IpAddress::from(owner.address());
+            assert_ne!(advertised.port, 0, "the advertised address carries the port the service is bound to");
+
+            let delegatee = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let dest = delegatee.local_addr().unwrap().to_string();
+            let cap = owner.create_capability().await;
+            let delegation = tokio::spawn(async move { cap.lock().await.delegate(dest.as_str().into()).await });
+
+            let mut buf = [0u8; 1024];
+            let len = delegatee.recv(&mut buf).await.unwrap();
+            let header = InsertCapHeader::try_from(&buf[..len]).unwrap();
+            assert_eq!({ header.object_owner_ip_address }, advertised.address);
+            assert_eq!({ header.object_owner_port }, advertised.port);
+
+            let response: Box<[u8; std::mem::size_of::<RequestResponseHeader>()]> =
+                RequestResponseHeader::construct(header.common.cap_id, header.common.stream_id, RESPONSE_OK).await.into();
+            delegatee.send_to(&response[..], owner.address()).await.unwrap();
+            delegation.await.unwrap().unwrap();
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_revocation_by_a_stranger_is_dropped() {
+            let owner = start(&[]).await;
+            let holder = start(&[]).await;
+
+            let cap = owner.create_capability().await;
+            let cap_id = cap.lock().await.cap_id;
+            cap.lock().await.delegate(holder.address().into()).await.unwrap();
+            assert!(holder.cap_exists(cap_id).await);
+            let mut events = holder.subscribe();
+
+            let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let packet: Box<[u8; std::mem::size_of::<RevokeCapHeader>()]> =
+                RevokeCapHeader::construct(&*cap.lock().await, IpAddress::from(owner.address()), 1).into();
+            stranger.send_to(&packet[..], holder.address()).await.unwrap();
+
+            // the first revocation the holder accepts is the one of the owner
+            let report = cap.lock().await.revoke(owner.clone()).await.unwrap();
+            assert!(report.confirmed.iter().any(|n| n == holder.address()));
+            let event = tokio::time::timeout(Duration::from_secs(2), events.recv()).await.unwrap().unwrap();
+            assert_eq!(event, ServiceEvent::CapRevoked { cap_id, revoker: owner.address().to_string() }, "only the owner and the delegator may revoke a copy");
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_view_follows_changes_of_the_cap() {
+            let service = start(&[]).await;
+            let cap = service.create_remote_capability_with_mac("127.0.0.1:1".into(), 42, [1; 16], 7).await;
+            let (_, view) = service.cap_table.get_view(42).await.unwrap();
+            assert_eq!(view.epoch(), 7);
+            assert_eq!(view.mac(), [1; 16]);
+
+            cap.lock().await.set_mac([2; 16], 8);
+            let (_, view) = service.cap_table.get_view(42).await.unwrap();
+            assert_eq!(view.epoch(), 8);
+            assert_eq!(view.mac(), [2; 16]);
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_concurrent_invocations_use_separate_streams() {
+            let owner = start(&[]).await;
+            let holder = start(&[]).await;
+            let cap = owner.create_capability().await;
+            let handler = RequestObject::new(Box::new(|_| {
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(())
+            })).await;
+            cap.lock().await.bind_req(Arc::new(Mutex::new(handler))).await;
+            let cap_id = cap.lock().await.cap_id;
+
+            let held = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, owner.capability_mac(cap_id), owner.epoch()).await;
+            let first = held.lock().await.clone();
+            let second = first.clone();
+            let (first, second) = tokio::join!(first.request_invoke(), second.request_invoke());
+            assert_eq!(first, Ok(()));
+            assert_eq!(second, Ok(()));
+            assert!(holder.response_notifiers.lock().await.is_empty(), "answered streams are closed");
+            assert!(holder.responses.lock().await.is_empty());
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_unanswered_streams_are_closed() {
+            let holder = start(&["--response-timeout-ms", "50"]).await;
+            // bound but never read, the owner does not answer
+            let silent_owner = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let owner = silent_owner.local_addr().unwrap().to_string();
+
+            let cap = holder.create_remote_capability_with_mac(owner.as_str().into(), 42, [0; 16], holder.epoch()).await;
+            assert_eq!(cap.lock().await.request_invoke().await, Err(InvokeError::Timeout));
+            assert!(holder.response_notifiers.lock().await.is_empty(), "timed out streams are closed");
+        }
+
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_invocation_mac_covers_the_sequence_number() {
+            let owner = start(&[]).await;
+            let cap = owner.create_capability().await;
+            cap.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await))).await;
+            let cap = cap.lock().await.clone();
+
+            let invoker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut buf = [0u8; 1024];
+            let header = RequestInvokeHeader::construct(&cap, &[], Flags::REQUIRE_RESPONSE, 5, 1);
+
+            let mut tampered = header;
+            tampered.sequence = 6;
+            let packet: Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]> = tampered.into();
+            invoker.send_to(&packet[..], owner.address()).await.unwrap();
+            let len = invoker.recv(&mut buf).await.unwrap();
+            assert!(CapInvalidHeader::try_from(&buf[..len]).is_ok(), "a modified sequence number must invalidate the invocation");
+
+            let packet: Box<[u8; std::mem::size_of::<RequestInvokeHeader>()]> = header.into();
+            invoker.send_to(&packet[..], owner.address()).await.unwrap();
+            let len = invoker.recv(&mut buf).await.unwrap();
+            assert_eq!({ RequestResponseHeader::try_from(&buf[..len]).unwrap().response_code }, RESPONSE_OK);
+        }
+    }
 }
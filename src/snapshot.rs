@@ -0,0 +1,260 @@
+pub mod tcap {
+    pub(crate) mod snapshot {
+        use std::{
+            fs,
+            io::{self, Write},
+            net::SocketAddr,
+            os::unix::fs::OpenOptionsExt,
+        };
+
+        use crate::{
+            auth::tcap::auth::{decode_hex, encode_hex},
+            capabilities::tcap::{CapID, CapType, Derivation},
+            packet_types::tcap::IpAddress,
+        };
+
+        const SNAPSHOT_HEADER: &str = "tcap-snapshot 1";
+
+        /// Persisted state of a capability owned by the service
+        #[derive(Clone, Debug)]
+        pub(crate) struct CapSnapshot {
+            pub(crate) cap_id: CapID,
+            pub(crate) cap_type: CapType,
+            /// name of the registered handler of request caps
+            pub(crate) handler: Option<String>,
+            /// contents of the memory object of memory caps
+            pub(crate) memory: Option<Vec<u8>>,
+            pub(crate) delegatees: Vec<IpAddress>,
+            pub(crate) derivations: Vec<Derivation>,
+        }
+
+        /// Owned caps and the MAC key authenticating their delegations.
+        ///
+        /// The file is line based, every cap line is followed by its delegatees and derivations:
+        /// ```text
+        /// tcap-snapshot 1
+        /// mac_key <hex>
+        /// epoch <epoch>
+        /// cap id=<cap id> type=<cap type> [handler=<name>] [memory=<hex>]
+        /// delegatee <address:port>
+        /// derivation <delegator address:port> <delegatee address:port> <delegation depth>
+        /// ```
+        #[derive(Clone, Debug)]
+        pub(crate) struct Snapshot {
+            pub(crate) mac_key: String,
+            /// epoch of the service, absent in snapshots written before epochs existed
+            pub(crate) epoch: Option<u32>,
+            pub(crate) caps: Vec<CapSnapshot>,
+        }
+
+        fn parse_address(val: &str) -> Result<IpAddress, String> {
+            let address: SocketAddr = val.parse().map_err(|e| format!("invalid address {:?}: {}", val, e))?;
+            if address.is_ipv6() {
+                return Err(format!("invalid address {:?}: only IPv4 is supported", val));
+            }
+            Ok(IpAddress::from(address))
+        }
+
+        fn parse_cap(fields: &[&str]) -> Result<CapSnapshot, String> {
+            let mut cap_id = None;
+            let mut cap_type = None;
+            let mut handler = None;
+            let mut memory = None;
+            for field in fields {
+                let (key, value) = field.split_once('=').ok_or(format!("expected <key>=<value>, got {:?}", field))?;
+                match key {
+                    "id" => cap_id = Some(value.parse::<CapID>().map_err(|e| e.to_string())?),
+                    "type" => cap_type = Some(CapType::from(value.parse::<u8>().map_err(|e| e.to_string())?)),
+                    "handler" => handler = Some(value.to_string()),
+                    "memory" if value.is_empty() => memory = Some(Vec::new()),
+                    "memory" => memory = Some(decode_hex(value)?),
+                    _ => return Err(format!("unknown cap field {:?}", key)),
+                }
+            }
+            Ok(CapSnapshot {
+                cap_id: cap_id.ok_or("cap without id")?,
+                cap_type: cap_type.ok_or("cap without type")?,
+                handler,
+                memory,
+                delegatees: Vec::new(),
+                derivations: Vec::new(),
+            })
+        }
+
+        impl Snapshot {
+            /// Read the snapshot at `path`, `None` if there is none yet
+            pub(crate) fn load(path: &str) -> io::Result<Option<Snapshot>> {
+                let text = match fs::read_to_string(path) {
+                    Ok(text) => text,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+                Snapshot::parse(&text)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid cap snapshot {:?}: {}", path, e)))
+            }
+
+            /// Replace the snapshot at `path`, a crash while writing leaves the previous snapshot intact.
+            /// The file holds the MAC key and is only readable by the owner of the process
+            pub(crate) fn store(&self, path: &str) -> io::Result<()> {
+                let tmp = format!("{}.tmp", path);
+                // the mode only applies to new files, a leftover of a crashed write could be readable by others
+                match fs::remove_file(&tmp) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                };
+                let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)?;
+                file.write_all(self.render().as_bytes())?;
+                file.sync_all()?;
+                fs::rename(tmp, path)
+            }
+
+            fn parse(text: &str) -> Result<Snapshot, String> {
+                let mut lines = text.lines();
+                if lines.next() != Some(SNAPSHOT_HEADER) {
+                    return Err(format!("expected {:?} as first line", SNAPSHOT_HEADER));
+                }
+                let mut mac_key = None;
+                let mut epoch = None;
+                let mut caps: Vec<CapSnapshot> = Vec::new();
+                for line in lines {
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    match fields.as_slice() {
+                        [] => continue,
+                        ["mac_key", key] => mac_key = Some(key.to_string()),
+                        ["epoch", val] => epoch = Some(val.parse::<u32>().map_err(|e| format!("invalid epoch {:?}: {}", val, e))?),
+                        ["cap", fields @ ..] => caps.push(parse_cap(fields)?),
+                        ["delegatee", delegatee] => caps
+                            .last_mut()
+                            .ok_or("delegatee before the first cap")?
+                            .delegatees
+                            .push(parse_address(delegatee)?),
+                        ["derivation", delegator, delegatee, depth] => caps
+                            .last_mut()
+                            .ok_or("derivation before the first cap")?
+                            .derivations
+                            .push(Derivation {
+                                delegator: parse_address(delegator)?,
+                                delegatee: parse_address(delegatee)?,
+                                depth: depth.parse::<u8>().map_err(|e| format!("invalid delegation depth {:?}: {}", depth, e))?,
+                            }),
+                        _ => return Err(format!("invalid line {:?}", line)),
+                    }
+                }
+                Ok(Snapshot {
+                    mac_key: mac_key.ok_or("snapshot without MAC key")?,
+                    epoch,
+                    caps,
+                })
+            }
+
+            fn render(&self) -> String {
+                let mut text = format!("{}\nmac_key {}\n", SNAPSHOT_HEADER, self.mac_key);
+                if let Some(epoch) = self.epoch {
+                    text.push_str(&format!("epoch {}\n", epoch));
+                }
+                for cap in self.caps.iter() {
+                    let cap_type: u8 = cap.cap_type.into();
+                    text.push_str(&format!("cap id={} type={}", cap.cap_id, cap_type));
+                    if let Some(handler) = cap.handler.as_ref() {
+                        text.push_str(&format!(" handler={}", handler));
+                    }
+                    if let Some(memory) = cap.memory.as_ref() {
+                        text.push_str(&format!(" memory={}", encode_hex(memory)));
+                    }
+                    text.push('\n');
+                    for delegatee in cap.delegatees.iter() {
+                        text.push_str(&format!("delegatee {}\n", String::from(*delegatee)));
+                    }
+                    for derivation in cap.derivations.iter() {
+                        text.push_str(&format!(
+                            "derivation {} {} {}\n",
+                            String::from(derivation.delegator),
+                            String::from(derivation.delegatee),
+                            derivation.depth
+                        ));
+                    }
+                }
+                text
+            }
+        }
+
+        mod tests {
+            #![allow(unused_imports)]
+            use super::{CapSnapshot, Snapshot};
+            use crate::{capabilities::tcap::{CapType, Derivation}, packet_types::tcap::IpAddress};
+
+            #[test]
+            fn test_snapshot_roundtrip() {
+                let owner = IpAddress::from("10.0.0.1:1234");
+                let delegatee = IpAddress::from("10.0.0.2:1234");
+                let snapshot = Snapshot {
+                    mac_key: "000102030405060708090a0b0c0d0e0f".to_string(),
+                    epoch: Some(7),
+                    caps: vec![
+                        CapSnapshot {
+                            cap_id: 42,
+                            cap_type: CapType::Request,
+                            handler: Some("add".to_string()),
+                            memory: None,
+                            delegatees: vec![delegatee],
+                            derivations: vec![
+                                Derivation { delegator: owner, delegatee, depth: 2 },
+                                Derivation { delegator: delegatee, delegatee: IpAddress::from("10.0.0.3:1234"), depth: 1 },
+                            ],
+                        },
+                        CapSnapshot {
+                            cap_id: u128::MAX,
+                            cap_type: CapType::Memory,
+                            handler: None,
+                            memory: Some(vec![0, 1, 254, 255]),
+                            delegatees: Vec::new(),
+                            derivations: Vec::new(),
+                        },
+                    ],
+                };
+
+                let restored = Snapshot::parse(&snapshot.render()).unwrap();
+                assert!(restored.render() == snapshot.render());
+                assert!(restored.mac_key == snapshot.mac_key);
+                assert!(restored.epoch == Some(7));
+                assert!(restored.caps.len() == 2);
+                assert!(restored.caps[0].handler.as_deref() == Some("add"));
+                assert!(restored.caps[0].derivations[1].delegator.same_node(&delegatee));
+                assert!(restored.caps[0].derivations[1].depth == 1);
+                assert!(restored.caps[1].cap_type == CapType::Memory);
+                assert!(restored.caps[1].memory == Some(vec![0, 1, 254, 255]));
+            }
+
+            #[test]
+            fn test_snapshot_rejects_invalid_files() {
+                assert!(Snapshot::parse("").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\ncap id=1 type=1\n").is_err(), "MAC key is required");
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\ndelegatee 10.0.0.2:1234\n").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\nepoch -1\n").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\ncap id=1\n").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\ncap id=1 type=2 memory=0\n").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\ncap id=1 type=1\ndelegatee nonsense\n").is_err());
+                assert!(Snapshot::parse("tcap-snapshot 1\nmac_key 00\ncap id=1 type=1\nderivation 10.0.0.1:1234 10.0.0.2:1234\n").is_err(),
+                    "derivations need a depth");
+            }
+
+            #[test]
+            fn test_snapshot_is_private() {
+                use std::os::unix::fs::PermissionsExt;
+
+                let path = std::env::temp_dir().join(format!("tcap-snapshot-test-{}", std::process::id()));
+                let path = path.to_str().unwrap();
+                // a leftover temporary file with a wider mode must not be reused
+                std::fs::write(format!("{}.tmp", path), "").unwrap();
+                std::fs::set_permissions(format!("{}.tmp", path), std::fs::Permissions::from_mode(0o644)).unwrap();
+
+                let snapshot = Snapshot { mac_key: "00".to_string(), epoch: None, caps: Vec::new() };
+                snapshot.store(path).unwrap();
+                let mode = std::fs::metadata(path).unwrap().permissions().mode();
+                std::fs::remove_file(path).unwrap();
+                assert!(mode & 0o777 == 0o600, "snapshot holding the MAC key has mode {:o}", mode & 0o777);
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+pub mod tcap {
+    pub(crate) mod transport {
+        use std::{collections::HashMap, io, net::SocketAddr, path::{Path, PathBuf}};
+
+        use log::{debug, warn};
+        use tokio::net::{UdpSocket, UnixDatagram};
+
+        use crate::{config::Config, crypto::tcap::crypto::PeerCiphers};
+
+        /// Datagram sockets of a service.
+        /// Peers configured as local peers are reached through the unix datagram socket,
+        /// all other peers through the UDP socket.
+        /// Packets exchanged with peers configured with a pre-shared key are encrypted on both sockets.
+        #[derive(Debug)]
+        pub(crate) struct Transport {
+            udp: UdpSocket,
+            unix: Option<UnixDatagram>,
+            unix_path: Option<PathBuf>,
+            local_peers: HashMap<SocketAddr, PathBuf>,
+            ciphers: PeerCiphers,
+        }
+
+        impl Transport {
+            pub(crate) async fn bind(config: &Config) -> io::Result<Transport> {
+                debug!("Binding UDP Socket to {:?}", config.address);
+                let udp = UdpSocket::bind(config.address.clone()).await?;
+                udp.bind_device(Some(config.interface.as_bytes()))?;
+
+                let unix_path = config.unix_socket.as_ref().map(PathBuf::from);
+                let unix = match unix_path.as_ref() {
+                    Some(path) => {
+                        debug!("Binding Unix Datagram Socket to {:?}", path);
+                        // remove the socket file of a previous run
+                        if path.exists() {
+                            std::fs::remove_file(path)?;
+                        }
+                        Some(UnixDatagram::bind(path)?)
+                    }
+                    None => None,
+                };
+
+                let mut local_peers = HashMap::new();
+                for (address, path) in config.local_peers.iter() {
+                    let address = address.parse::<SocketAddr>()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid local peer address {:?}: {}", address, e)))?;
+                    local_peers.insert(address, PathBuf::from(path));
+                }
+
+                let ciphers = PeerCiphers::from_config(config)?;
+
+                Ok(Transport {
+                    udp,
+                    unix,
+                    unix_path,
+                    local_peers,
+                    ciphers,
+                })
+            }
+
+            pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+                self.udp.local_addr()
+            }
+
+            fn local_path(&self, dest: &str) -> Option<&PathBuf> {
+                self.unix.as_ref()?;
+                let dest = dest.parse::<SocketAddr>().ok()?;
+                self.local_peers.get(&dest)
+            }
+
+            fn local_peer(&self, path: &Path) -> Option<SocketAddr> {
+                self.local_peers
+                    .iter()
+                    .find(|(_, p)| p.as_path() == path)
+                    .map(|(address, _)| *address)
+            }
+
+            pub(crate) async fn send_to(&self, data: &[u8], dest: &str) -> io::Result<usize> {
+                let sealed = self.ciphers.seal(dest, data);
+                let data = sealed.as_deref().unwrap_or(data);
+                match self.local_path(dest) {
+                    Some(path) => self.unix.as_ref().unwrap().send_to(data, path).await,
+                    None => self.udp.send_to(data, dest).await,
+                }
+            }
+
+            /// Receive the next datagram from either socket and decrypt it if the sender has a pre-shared key.
+            /// Datagrams failing authentication are dropped.
+            pub(crate) async fn recv_buf_from(&self, buf: &mut Vec<u8>) -> io::Result<(usize, SocketAddr)> {
+                let capacity = buf.capacity();
+                loop {
+                    let (received_bytes, sender) = self.recv_datagram(buf).await?;
+                    buf.truncate(received_bytes);
+                    match self.ciphers.open(&sender, std::mem::take(buf)) {
+                        Ok(packet) => {
+                            *buf = packet;
+                            return Ok((buf.len(), sender));
+                        }
+                        Err(()) => {
+                            warn!("dropping datagram from {:?} failing authentication", sender);
+                            buf.reserve(capacity);
+                        }
+                    };
+                }
+            }
+
+            /// Receive the next datagram from either socket.
+            /// Datagrams on the unix socket are reported with the address of the local peer that sent them.
+            async fn recv_datagram(&self, buf: &mut Vec<u8>) -> io::Result<(usize, SocketAddr)> {
+                let unix = match self.unix.as_ref() {
+                    Some(unix) => unix,
+                    None => return self.udp.recv_buf_from(buf).await,
+                };
+
+                loop {
+                    tokio::select! {
+                        ready = self.udp.readable() => {
+                            ready?;
+                            match self.udp.try_recv_buf_from(buf) {
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                                res => return res,
+                            }
+                        }
+                        ready = unix.readable() => {
+                            ready?;
+                            let (received_bytes, sender) = match unix.try_recv_buf_from(buf) {
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                                res => res?,
+                            };
+
+                            match sender.as_pathname().and_then(|path| self.local_peer(path)) {
+                                Some(sender) => return Ok((received_bytes, sender)),
+                                None => {
+                                    warn!("dropping datagram from unknown unix socket {:?}", sender);
+                                    buf.clear();
+                                }
+                            };
+                        }
+                    }
+                }
+            }
+        }
+
+        impl Drop for Transport {
+            fn drop(&mut self) {
+                if let Some(path) = self.unix_path.as_ref() {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+    }
+}
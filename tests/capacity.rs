@@ -0,0 +1,86 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{start, wait_for};
+use tcap::events::tcap::events::ServiceEvent;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_owned_caps_are_limited() {
+    let owner = start(&["--max-owned-caps", "2"]).await;
+
+    let cap = owner.try_create_capability().await.unwrap();
+    owner.try_create_capability_with_id(7).await.unwrap();
+    assert!(owner.try_create_capability().await.is_err());
+    assert!(owner.try_create_capability_with_id(8).await.is_err());
+    assert_eq!(owner.owned_capability_count(), 2);
+
+    owner.delete_capability(cap).await;
+    assert_eq!(owner.owned_capability_count(), 1);
+    assert!(owner.try_create_capability().await.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_full_table_rejects_delegations() {
+    let owner = start(&[]).await;
+    let holder = start(&["--max-received-caps", "1"]).await;
+    let mut events = owner.subscribe();
+
+    let held = owner.create_capability().await;
+    held.lock().await.delegate(holder.address().into()).await.unwrap();
+    let rejected = owner.create_capability().await;
+    let rejected_id = rejected.lock().await.cap_id;
+    let e = rejected.lock().await.delegate(holder.address().into()).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::Other);
+
+    let event = wait_for(&mut events, |event| matches!(event, ServiceEvent::DelegationRejected { .. })).await;
+    assert_eq!(event, ServiceEvent::DelegationRejected { cap_id: rejected_id, delegatee: holder.address().to_string() });
+    assert!(rejected.lock().await.holders().await.is_empty());
+    assert!(!holder.cap_exists(rejected_id).await);
+    assert!(holder.cap_exists(held.lock().await.cap_id).await);
+    assert_eq!(holder.received_capability_count(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_full_table_rejects_delegations_of_holders() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let full = start(&["--max-received-caps", "1"]).await;
+
+    owner.create_capability().await.lock().await.delegate(full.address().into()).await.unwrap();
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    let e = held.lock().await.delegate(full.address().into()).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::Other, "the owner forwards the rejection to the holder");
+    assert!(held.lock().await.delegatees().await.is_empty());
+    assert_eq!(cap.lock().await.derivation_tree().await.len(), 1);
+    assert!(!full.cap_exists(cap_id).await);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_expired_lease_is_evicted() {
+    let owner = start(&[]).await;
+    let holder = start(&["--max-received-caps", "1"]).await;
+    let mut events = holder.subscribe();
+
+    let leased = owner.create_capability().await;
+    let leased_id = leased.lock().await.cap_id;
+    leased.lock().await.delegate_with_lease(holder.address().into(), Duration::from_millis(100)).await.unwrap();
+    // an owner that forgot the cap does not revoke the lease when it expires, the holder keeps its copy
+    owner.delete_capability(leased).await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(holder.cap_exists(leased_id).await);
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    assert!(!holder.cap_exists(leased_id).await);
+    assert!(holder.cap_exists(cap_id).await);
+    assert_eq!(holder.received_capability_count(), 1);
+    let evicted = std::iter::from_fn(|| events.try_recv().ok()).any(|event| event == ServiceEvent::CapEvicted { cap_id: leased_id });
+    assert!(evicted);
+}
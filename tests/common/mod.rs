@@ -0,0 +1,51 @@
+// each test target uses a subset of the helpers
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use clap::Parser;
+use tcap::{config::Config, events::tcap::events::ServiceEvent, service::tcap::Service};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Time to wait for an expected event before failing the test
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Config of a service on the loopback interface bound to a free port
+pub fn config(args: &[&str]) -> Config {
+    let mut argv = vec!["tcap", "-i", "lo", "-a", "127.0.0.1:0", "-s", "127.0.0.1:1"];
+    argv.extend_from_slice(args);
+    Config::parse_from(argv)
+}
+
+/// Start a service on a free port and run it in the background, `Service::address` tells the port
+pub async fn start(args: &[&str]) -> Service {
+    let service = Service::new(config(args)).await;
+    let runner = service.clone();
+    tokio::spawn(async move { runner.run().await });
+    service
+}
+
+/// Next event matching `filter` published within `timeout`, None if there is none
+pub async fn next_event<F>(events: &mut broadcast::Receiver<ServiceEvent>, timeout: Duration, filter: F) -> Option<ServiceEvent>
+where
+    F: Fn(&ServiceEvent) -> bool,
+{
+    let next = async {
+        loop {
+            match events.recv().await {
+                Ok(event) if filter(&event) => return event,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => panic!("service stopped publishing events"),
+            }
+        }
+    };
+    tokio::time::timeout(timeout, next).await.ok()
+}
+
+/// Wait for the next event matching `filter`, panics if none is published within `EVENT_TIMEOUT`
+pub async fn wait_for<F>(events: &mut broadcast::Receiver<ServiceEvent>, filter: F) -> ServiceEvent
+where
+    F: Fn(&ServiceEvent) -> bool,
+{
+    next_event(events, EVENT_TIMEOUT, filter).await.expect("expected event was not published")
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::start;
+use tcap::capabilities::tcap::{InvokeError, Rights};
+use tcap::object::tcap::object::RequestObject;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_redelegation_narrows_rights() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let delegatee = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    let object = Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await));
+    cap.lock().await.bind_req(object).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    held.lock().await.delegate_with_rights(delegatee.address().into(), Rights::READ | Rights::DELEGATE).await.unwrap();
+
+    let derived = delegatee.get_capability(cap_id).await.unwrap();
+    assert_eq!(derived.lock().await.rights(), Rights::READ | Rights::DELEGATE);
+    assert_eq!(derived.lock().await.request_invoke().await, Err(InvokeError::PermissionDenied));
+    let e = derived.lock().await.delegate_with_rights(owner.address().into(), Rights::INVOKE).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+
+    let tree = cap.lock().await.derivation_tree().await;
+    assert_eq!(tree.len(), 2, "the owner records the delegation by the holder");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_redelegation_requires_delegate_right() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let delegatee = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate_with_rights(holder.address().into(), Rights::INVOKE).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    let e = held.lock().await.delegate(delegatee.address().into()).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(cap.lock().await.derivation_tree().await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_owner_records_delegation_depth() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let delegatee = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate_with_depth(holder.address().into(), Rights::all(), 1).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    held.lock().await.delegate(delegatee.address().into()).await.unwrap();
+
+    let derived = delegatee.get_capability(cap_id).await.unwrap();
+    assert_eq!(derived.lock().await.delegation_depth(), 0);
+    let depths: Vec<u8> = cap.lock().await.derivation_tree().await.iter().map(|d| d.depth).collect();
+    assert_eq!(depths, vec![1, 0]);
+}
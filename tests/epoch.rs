@@ -0,0 +1,55 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::start;
+use tcap::capabilities::tcap::{InvokeError, UNKNOWN_EPOCH};
+use tcap::object::tcap::object::{MemoryObject, RequestObject};
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stale_caps_are_rejected_after_a_reset() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    let mac = owner.capability_mac(cap_id);
+    let epoch = owner.epoch();
+
+    owner.reset().await;
+    let cap = owner.create_capability_with_id(cap_id).await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await))).await;
+
+    let stale = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, mac, epoch).await;
+    assert_eq!(stale.lock().await.request_invoke().await, Err(InvokeError::StaleEpoch));
+
+    // without a valid MAC the owner does not reveal that the cap is stale
+    let forged = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, [0; 16], epoch).await;
+    assert_eq!(forged.lock().await.request_invoke().await, Err(InvokeError::Invalid));
+
+    // claiming not to know the epoch does not help, the MAC covers it
+    let unknown = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, mac, UNKNOWN_EPOCH).await;
+    assert!(unknown.lock().await.request_invoke().await.is_err());
+
+    let current = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, owner.capability_mac(cap_id), owner.epoch()).await;
+    assert_eq!(current.lock().await.request_invoke().await, Ok(()));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_stale_memory_copy_fails() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(vec![1, 2, 3]).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    owner.reset().await;
+    let cap = owner.create_capability_with_id(cap_id).await;
+    cap.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(vec![4, 5, 6]).await))).await;
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    assert_eq!(held.lock().await.get_buffer().await.err(), Some(InvokeError::StaleEpoch));
+}
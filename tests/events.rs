@@ -0,0 +1,55 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{start, wait_for};
+use tcap::capabilities::tcap::InvokeError;
+use tcap::events::tcap::events::ServiceEvent;
+use tcap::object::tcap::object::RequestObject;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_events_follow_the_life_of_a_capability() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let mut owner_events = owner.subscribe();
+    let mut holder_events = holder.subscribe();
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+    let inserted = wait_for(&mut holder_events, |event| matches!(event, ServiceEvent::CapInserted { .. })).await;
+    assert_eq!(inserted, ServiceEvent::CapInserted { cap_id, delegator: owner.address().to_string() });
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    held.lock().await.request_invoke().await.unwrap();
+    let invoked = wait_for(&mut owner_events, |event| matches!(event, ServiceEvent::Invoked { .. })).await;
+    assert_eq!(invoked, ServiceEvent::Invoked { cap_id, caller: holder.address().to_string(), success: true });
+
+    cap.lock().await.revoke(owner.clone()).await.unwrap();
+    let revoked = wait_for(&mut holder_events, |event| matches!(event, ServiceEvent::CapRevoked { .. })).await;
+    assert_eq!(revoked, ServiceEvent::CapRevoked { cap_id, revoker: owner.address().to_string() });
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_failed_and_rejected_invocations_are_published() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let mut events = owner.subscribe();
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Err(()))).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    assert_eq!(held.lock().await.request_invoke().await, Err(InvokeError::Failed));
+    let invoked = wait_for(&mut events, |event| matches!(event, ServiceEvent::Invoked { .. })).await;
+    assert_eq!(invoked, ServiceEvent::Invoked { cap_id, caller: holder.address().to_string(), success: false });
+
+    let unknown = holder.create_remote_capability_with_mac(owner.address().into(), 42, [0; 16], owner.epoch()).await;
+    assert_eq!(unknown.lock().await.request_invoke().await, Err(InvokeError::Invalid));
+    let invalid = wait_for(&mut events, |event| matches!(event, ServiceEvent::CapInvalid { .. })).await;
+    assert_eq!(invalid, ServiceEvent::CapInvalid { cap_id: 42, peer: holder.address().to_string() });
+}
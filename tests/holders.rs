@@ -0,0 +1,54 @@
+mod common;
+
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+use common::{start, wait_for};
+use tcap::events::tcap::events::ServiceEvent;
+use tcap::object::tcap::object::MemoryObject;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unheld_objects_are_reclaimed() {
+    let owner = start(&["--reclaim-unheld-objects"]).await;
+    let holder = start(&[]).await;
+    let derived = start(&[]).await;
+    let mut events = owner.subscribe();
+    let reclaimed = Arc::new(AtomicUsize::new(0));
+    let counter = reclaimed.clone();
+    owner.on_reclaim(Box::new(move |_, _| { counter.fetch_add(1, Ordering::SeqCst); })).await;
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(vec![1]).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+    holder.get_capability(cap_id).await.unwrap().lock().await.delegate(derived.address().into()).await.unwrap();
+    assert_eq!(cap.lock().await.holders().await.len(), 2);
+
+    holder.close_capability(holder.get_capability(cap_id).await.unwrap()).await;
+    wait_for(&mut events, |event| matches!(event, ServiceEvent::CapClosed { .. })).await;
+    assert_eq!(cap.lock().await.holders().await.len(), 1, "closing a copy removes only its holder");
+    assert!(owner.cap_exists(cap_id).await, "the derived copy is still held");
+
+    derived.close_capability(derived.get_capability(cap_id).await.unwrap()).await;
+    wait_for(&mut events, |event| matches!(event, ServiceEvent::CapClosed { .. })).await;
+    assert!(!owner.cap_exists(cap_id).await);
+    assert_eq!(reclaimed.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unheld_objects_are_kept_without_reclaiming() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let mut events = owner.subscribe();
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    holder.close_capability(holder.get_capability(cap_id).await.unwrap()).await;
+    let closed = wait_for(&mut events, |event| matches!(event, ServiceEvent::CapClosed { .. })).await;
+    assert_eq!(closed, ServiceEvent::CapClosed { cap_id, holder: holder.address().to_string() });
+    assert!(cap.lock().await.holders().await.is_empty());
+    assert!(owner.cap_exists(cap_id).await);
+    assert!(!holder.cap_exists(cap_id).await);
+}
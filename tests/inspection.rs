@@ -0,0 +1,57 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::start;
+use tcap::capabilities::tcap::{CapID, CapType, Capability};
+use tcap::object::tcap::object::{MemoryObject, RequestObject};
+use tokio::sync::Mutex;
+
+async fn ids(caps: Vec<Arc<Mutex<Capability>>>) -> Vec<CapID> {
+    let mut ids = Vec::new();
+    for cap in caps {
+        ids.push(cap.lock().await.cap_id);
+    }
+    ids.sort();
+    ids
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_capabilities_are_filtered() {
+    let node = start(&[]).await;
+    let first = start(&[]).await;
+    let second = start(&[]).await;
+
+    let request = node.create_capability().await;
+    request.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await))).await;
+    let request_id = request.lock().await.cap_id;
+    request.lock().await.delegate(first.address().into()).await.unwrap();
+
+    let memory = node.create_capability().await;
+    memory.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(vec![1]).await))).await;
+    let memory_id = memory.lock().await.cap_id;
+    memory.lock().await.delegate(first.address().into()).await.unwrap();
+    memory.lock().await.delegate(second.address().into()).await.unwrap();
+
+    let received = first.create_capability().await;
+    let received_id = received.lock().await.cap_id;
+    received.lock().await.delegate(node.address().into()).await.unwrap();
+
+    let mut all = vec![request_id, memory_id, received_id];
+    all.sort();
+    assert_eq!(ids(node.capabilities().await).await, all);
+    assert!(node.get_capability(received_id).await.is_some());
+    assert!(node.get_capability(CapID::MAX).await.is_none());
+
+    assert_eq!(ids(node.capabilities_of_type(CapType::Request).await).await, vec![request_id]);
+    assert_eq!(ids(node.capabilities_of_type(CapType::Memory).await).await, vec![memory_id]);
+
+    let mut owned = vec![request_id, memory_id];
+    owned.sort();
+    assert_eq!(ids(node.capabilities_owned_by(&node.address().into()).await).await, owned);
+    assert_eq!(ids(node.capabilities_owned_by(&first.address().into()).await).await, vec![received_id]);
+
+    assert_eq!(ids(node.capabilities_delegated_to(&first.address().into()).await).await, owned);
+    assert_eq!(ids(node.capabilities_delegated_to(&second.address().into()).await).await, vec![memory_id]);
+    assert!(node.capabilities_delegated_to(&node.address().into()).await.is_empty());
+}
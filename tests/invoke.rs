@@ -0,0 +1,38 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::start;
+use tcap::capabilities::tcap::InvokeError;
+use tcap::object::tcap::object::RequestObject;
+use tokio::sync::Mutex;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unanswered_invocation_times_out() {
+    let holder = start(&["--response-timeout-ms", "100"]).await;
+    // bound but never read, the owner does not answer
+    let silent_owner = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let owner = silent_owner.local_addr().unwrap().to_string();
+
+    let cap = holder.create_remote_capability_with_mac(owner.as_str().into(), 42, [0; 16], holder.epoch()).await;
+    assert_eq!(cap.lock().await.request_invoke().await, Err(InvokeError::Timeout));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_only_delegatees_may_invoke() {
+    let owner = start(&["--enforce-delegatees"]).await;
+    let holder = start(&[]).await;
+    let stranger = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(RequestObject::new(Box::new(|_| Ok(()))).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    assert_eq!(held.lock().await.request_invoke().await, Ok(()));
+
+    // a valid MAC does not help a node the cap was never delegated to
+    let leaked = stranger.create_remote_capability_with_mac(owner.address().into(), cap_id, owner.capability_mac(cap_id), owner.epoch()).await;
+    assert_eq!(leaked.lock().await.request_invoke().await, Err(InvokeError::Invalid));
+}
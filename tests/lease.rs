@@ -0,0 +1,47 @@
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{start, wait_for};
+use tcap::events::tcap::events::ServiceEvent;
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_lease_renewal_is_bounded_by_the_granted_duration() {
+    let owner = start(&[]).await;
+    let holder = start(&[]).await;
+    let mut events = holder.subscribe();
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate_with_lease(holder.address().into(), Duration::from_millis(500)).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    let renewed_at = now_ms();
+    held.lock().await.renew_lease(Duration::MAX).await.unwrap();
+    let expiry = held.lock().await.lease_expiry_ms().unwrap();
+    assert!(expiry <= renewed_at + 500 + 100, "renewal must be clamped to the granted duration, got {:?}", expiry - renewed_at);
+
+    // a lease renewed for the requested duration would not expire before the wait times out
+    wait_for(&mut events, |event| matches!(event, ServiceEvent::CapRevoked { .. })).await;
+    assert!(holder.get_capability(cap_id).await.is_none(), "the clamped lease must expire");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_lease_renewal_times_out() {
+    let owner = start(&[]).await;
+    let holder = start(&["--response-timeout-ms", "100"]).await;
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate_with_lease(holder.address().into(), Duration::from_secs(10)).await.unwrap();
+
+    // a stopped owner no longer answers renewals
+    let held = holder.get_capability(cap_id).await.unwrap();
+    owner.terminate().await;
+    let renewal = tokio::time::timeout(Duration::from_secs(2), async { held.lock().await.renew_lease(Duration::from_secs(1)).await });
+    assert!(renewal.await.expect("renew_lease must not wait forever").is_err());
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{start, wait_for};
+use tcap::events::tcap::events::ServiceEvent;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_revocation_cascades_through_the_derivation_tree() {
+    let owner = start(&[]).await;
+    let first = start(&[]).await;
+    let second = start(&[]).await;
+    let third = start(&[]).await;
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(first.address().into()).await.unwrap();
+    let held = first.get_capability(cap_id).await.unwrap();
+    held.lock().await.delegate(second.address().into()).await.unwrap();
+    let held = second.get_capability(cap_id).await.unwrap();
+    held.lock().await.delegate(third.address().into()).await.unwrap();
+    assert!(third.cap_exists(cap_id).await);
+
+    let report = cap.lock().await.revoke(owner.clone()).await.unwrap();
+    for node in [first.address(), second.address(), third.address()] {
+        assert!(report.confirmed.iter().any(|n| n == node), "{} must acknowledge the revocation", node);
+    }
+    for node in [&first, &second, &third] {
+        assert!(!node.cap_exists(cap_id).await, "all derived copies must be revoked");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_expired_lease_revokes_derived_copies() {
+    let owner = start(&[]).await;
+    let lessee = start(&[]).await;
+    let derived = start(&[]).await;
+    let other = start(&[]).await;
+    let mut lessee_events = lessee.subscribe();
+    let mut derived_events = derived.subscribe();
+
+    let cap = owner.create_capability().await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate_with_lease(lessee.address().into(), Duration::from_millis(200)).await.unwrap();
+    cap.lock().await.delegate(other.address().into()).await.unwrap();
+    let held = lessee.get_capability(cap_id).await.unwrap();
+    held.lock().await.delegate(derived.address().into()).await.unwrap();
+    assert!(derived.cap_exists(cap_id).await);
+
+    let revoked = |event: &ServiceEvent| matches!(event, ServiceEvent::CapRevoked { cap_id: id, .. } if *id == cap_id);
+    wait_for(&mut lessee_events, revoked).await;
+    wait_for(&mut derived_events, revoked).await;
+    assert!(!lessee.cap_exists(cap_id).await);
+    assert!(!derived.cap_exists(cap_id).await, "copies derived from an expired lease must be revoked");
+    assert!(other.cap_exists(cap_id).await, "copies outside of the lessee's subtree stay valid");
+    assert_eq!(cap.lock().await.derivation_tree().await.len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_revocation_reports_unacknowledged_nodes() {
+    let owner = start(&["--revoke-ack-timeout-ms", "50", "--revoke-retries", "2", "--response-timeout-ms", "50"]).await;
+    let holder = start(&[]).await;
+    // bound but never read, neither the delegation nor the revocation is acknowledged
+    let silent_peer = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent = silent_peer.local_addr().unwrap().to_string();
+
+    let cap = owner.create_capability().await;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+    let e = cap.lock().await.delegate(silent.as_str().into()).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+
+    let started = std::time::Instant::now();
+    let report = cap.lock().await.revoke(owner.clone()).await.unwrap();
+    assert!(report.confirmed.iter().any(|n| n == holder.address()));
+    assert!(report.timed_out.contains(&silent));
+    assert!(!report.confirmed.contains(&silent));
+    assert!(started.elapsed() >= Duration::from_millis(150), "unacknowledged revocations are resent twice");
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{config, start};
+use tcap::object::tcap::object::RequestObject;
+use tcap::service::tcap::{Service, ShutdownReport};
+use tokio::sync::{Mutex, Notify};
+
+/// Owner of a cap whose handler runs for `handler_ms` and signals `started` when it is entered
+async fn slow_owner(args: &[&str], handler_ms: u64, started: Arc<Notify>) -> (Service, u128) {
+    let owner = Service::new(config(args)).await;
+    let cap = owner.create_capability().await;
+    let handler = RequestObject::new(Box::new(move |_| {
+        started.notify_one();
+        std::thread::sleep(Duration::from_millis(handler_ms));
+        Ok(())
+    })).await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(handler))).await;
+    let cap_id = cap.lock().await.cap_id;
+    (owner, cap_id)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_shutdown_drains_running_handlers() {
+    let started = Arc::new(Notify::new());
+    // the switch does not acknowledge revocations, do not wait for it on shutdown
+    let (owner, cap_id) = slow_owner(&["--shutdown-timeout-ms", "5000", "--revoke-retries", "0", "--revoke-ack-timeout-ms", "10"], 1000, started.clone()).await;
+    let runner = owner.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+    let holder = start(&["--response-timeout-ms", "5000"]).await;
+
+    let cap = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, owner.capability_mac(cap_id), owner.epoch()).await;
+    let late = cap.lock().await.clone();
+    let invocation = tokio::spawn(async move { cap.lock().await.request_invoke().await });
+    started.notified().await;
+
+    owner.terminate().await;
+    // invocations arriving after the shutdown started are rejected, the running one is completed
+    late.request_invoke_with_continuation_no_wait(vec![]).await.unwrap();
+    let report = run.await.unwrap().unwrap();
+    assert_eq!(report, ShutdownReport { rejected_packets: 1, ..ShutdownReport::default() });
+    assert_eq!(invocation.await.unwrap(), Ok(()), "the response of the drained handler is sent");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_shutdown_reports_unfinished_handlers() {
+    let started = Arc::new(Notify::new());
+    let (owner, cap_id) = slow_owner(&["--shutdown-timeout-ms", "50", "--revoke-retries", "0", "--revoke-ack-timeout-ms", "10"], 1000, started.clone()).await;
+    let runner = owner.clone();
+    let run = tokio::spawn(async move { runner.run().await });
+    let holder = start(&["--response-timeout-ms", "100"]).await;
+
+    let cap = holder.create_remote_capability_with_mac(owner.address().into(), cap_id, owner.capability_mac(cap_id), owner.epoch()).await;
+    tokio::spawn(async move { cap.lock().await.request_invoke().await });
+    started.notified().await;
+
+    owner.terminate().await;
+    let report = run.await.unwrap().unwrap();
+    assert_eq!(report.unfinished_handlers, 1, "the handler outlived the shutdown deadline");
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+use common::start;
+use tcap::object::tcap::object::{MemoryObject, RequestObject};
+use tcap::service::tcap::Service;
+use tokio::sync::Mutex;
+
+fn socket_path(name: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("tcap-transport-{}-{}", std::process::id(), name));
+    path.to_str().unwrap().to_string()
+}
+
+/// Owner and holder that reach each other only through their unix sockets.
+/// The holder advertises the address of `silent_peer`, which never answers, so traffic falling back to UDP is lost
+async fn co_located(name: &str, silent_peer: &std::net::UdpSocket) -> (Service, Service) {
+    let (owner_path, holder_path) = (socket_path(&format!("{}-owner", name)), socket_path(&format!("{}-holder", name)));
+    let holder_address = silent_peer.local_addr().unwrap().to_string();
+
+    let holder_peer = format!("{}={}", holder_address, holder_path);
+    let owner = start(&["--unix-socket", owner_path.as_str(), "--local-peer", holder_peer.as_str()]).await;
+    let owner_peer = format!("{}={}", owner.address(), owner_path);
+    let holder = start(&["--unix-socket", holder_path.as_str(), "--advertise-address", holder_address.as_str(), "--local-peer", owner_peer.as_str()]).await;
+    (owner, holder)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_invocation_over_unix_socket() {
+    let silent_peer = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let (owner, holder) = co_located("invoke", &silent_peer).await;
+    let invoked = Arc::new(AtomicUsize::new(0));
+    let counter = invoked.clone();
+
+    let cap = owner.create_capability().await;
+    let object = RequestObject::new(Box::new(move |_| { counter.fetch_add(1, Ordering::SeqCst); Ok(()) })).await;
+    cap.lock().await.bind_req(Arc::new(Mutex::new(object))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    assert_eq!(held.lock().await.request_invoke().await, Ok(()));
+    assert_eq!(invoked.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_memory_copy_over_unix_socket() {
+    let silent_peer = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let (owner, holder) = co_located("copy", &silent_peer).await;
+
+    let cap = owner.create_capability().await;
+    let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+    cap.lock().await.bind_mem(Arc::new(Mutex::new(MemoryObject::new(data.clone()).await))).await;
+    let cap_id = cap.lock().await.cap_id;
+    cap.lock().await.delegate(holder.address().into()).await.unwrap();
+
+    let held = holder.get_capability(cap_id).await.unwrap();
+    let buffer = held.lock().await.get_buffer().await.unwrap();
+    assert_eq!(buffer.lock().await.data(), data);
+}